@@ -0,0 +1,31 @@
+/*
+ * Adaptive comfort model after ASHRAE 55: the comfortable indoor operative temperature
+ * follows the prevailing mean outdoor temperature,
+ *
+ *   t_comf = 0.31 * t_outdoor_mean + 17.8
+ *
+ * The model is defined for mean outdoor temperatures of 10 - 33.5 C; outside that range
+ * the mean is clamped to its ends.
+ */
+
+const MIN_OUTDOOR_MEAN: f64 = 10.0;
+const MAX_OUTDOOR_MEAN: f64 = 33.5;
+
+/**
+ * returns the comfort temperature for the given running mean of outdoor temperature
+ */
+pub(crate) fn comfort_temp(outdoor_mean: f64) -> f64 {
+    0.31 * outdoor_mean.clamp(MIN_OUTDOOR_MEAN, MAX_OUTDOOR_MEAN) + 17.8
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn adaptive_comfort_temp() {
+        assert!((comfort_temp(20.0) - 24.0).abs() < 0.001);
+        assert!((comfort_temp(-5.0) - 20.9).abs() < 0.001);
+        assert!((comfort_temp(40.0) - comfort_temp(33.5)).abs() < 0.001);
+    }
+}
@@ -0,0 +1,306 @@
+/*
+ * Indoor air quality monitoring on Awair readings.
+ *
+ * Mold risk: the share (0 - 100) of the last 24 hours during which relative humidity
+ * stayed at or above `mold_humidity`. Gaps between readings longer than the control
+ * interval (e.g. outside the control window) count only up to the control interval.
+ *
+ * CO2: a notification is sent when CO2 stays above `co2_alert` ppm for `co2_alert_minutes`.
+ *
+ * PM2.5: an event (e.g. wildfire smoke) starts when PM2.5 reaches `pm25_alert` and ends when it
+ * falls below PM25_CLEAR_RATIO of it. During the event fan circulation may be turned on.
+ * Pausing fresh-air ventilation is left out: the thermostat API used here has no control of
+ * the ventilator, so outdoor air keeps coming in at its own schedule.
+ *
+ * VOC: a spike is a reading `voc_spike` times the baseline (a moving average of readings
+ * outside spikes) and at least VOC_SPIKE_MIN ppb above it. During a spike fan circulation
+ * may be turned off, unless a PM2.5 event wants it on.
+ *
+ * Both features restore the fan circulation mode from before the first of them changed it. When
+ * one ends while the other is still on, the fan is left to the other, which restores it later.
+ */
+use serde::{Deserialize, Serialize};
+use super::*;
+
+const MOLD_WINDOW: i64 = 24 * 60 * 60;
+/* a PM2.5 event ends below this ratio of pm25_alert, so it does not flap around the threshold */
+const PM25_CLEAR_RATIO: f64 = 0.8;
+/* smallest VOC rise (ppb) treated as a spike, so low baselines do not trigger on noise */
+const VOC_SPIKE_MIN: f64 = 100.0;
+/* weight of a new reading in the VOC baseline */
+const VOC_BASELINE_WEIGHT: f64 = 0.2;
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct AirState {
+    /* (unix time, relative humidity) over the last day */
+    humid: Vec<(i64, f64)>,
+    mold_alerted: bool,
+    /* unix time since CO2 has been above the threshold */
+    co2_above_since: Option<i64>,
+    co2_alerted: bool,
+    pm25_event: bool,
+    /* fan circulation mode from before a PM2.5 event or a VOC spike changed it */
+    saved_fan_circulate: Option<u32>,
+    voc_baseline: Option<f64>,
+    voc_spike: bool,
+}
+
+impl AirState {
+    fn record_humidity(&mut self, now: i64, rh: f64) {
+        self.humid.retain(|(at, _)| *at > now - MOLD_WINDOW);
+        self.humid.push((now, rh));
+    }
+
+    /**
+     * returns the mold-risk index for humidity samples recorded so far
+     */
+    fn mold_risk(&self, threshold: f64, max_gap: u32) -> f64 {
+        let humid_secs: i64 = self.humid.windows(2)
+            .filter(|w| w[0].1 >= threshold)
+            .map(|w| (w[1].0 - w[0].0).min(max_gap as i64 * 60))
+            .sum();
+        (humid_secs as f64 / MOLD_WINDOW as f64 * 100.0).min(100.0)
+    }
+}
+
+/**
+ * records humidity and returns the mold-risk index, alerting once when it reaches `mold_risk_alert`
+ */
+pub(crate) fn check_mold(config: &Config, air: &mut AirState, rh: f64, now: i64) -> Option<f64> {
+    let threshold = config.mold_humidity?;
+    air.record_humidity(now, rh);
+    let risk = air.mold_risk(threshold, config.control_interval);
+    let high = config.mold_risk_alert.is_some_and(|limit| risk >= limit);
+    if high && !air.mold_alerted {
+        alert(config, &format!("mold risk: humidity was {}% or higher for {:.0}% of the last 24 hours", threshold, risk));
+    }
+    air.mold_alerted = high;
+    Some(risk)
+}
+
+/**
+ * notifies once when CO2 has stayed above `co2_alert` for `co2_alert_minutes`
+ */
+pub(crate) fn check_co2(config: &Config, air: &mut AirState, co2: f64, now: i64) {
+    let limit = match config.co2_alert {
+        Some(l) => l,
+        None => return,
+    };
+    if co2 <= limit {
+        air.co2_above_since = None;
+        air.co2_alerted = false;
+        return;
+    }
+    let since = *air.co2_above_since.get_or_insert(now);
+    if !air.co2_alerted && now - since >= config.co2_alert_minutes as i64 * 60 {
+        alert(config, &format!("CO2 has been above {} ppm for {} minutes (now {:.0} ppm)", limit, (now - since) / 60, co2));
+        air.co2_alerted = true;
+    }
+}
+
+/**
+ * starts or ends a PM2.5 event, notifying and switching fan circulation as configured
+ */
+pub(crate) async fn check_pm25(config: &Config, air: &mut AirState, thermostat: &mut dyn Thermostat, pm25: f64) {
+    let limit = match config.pm25_alert {
+        Some(l) => l,
+        None => return,
+    };
+    if !air.pm25_event && pm25 >= limit {
+        alert(config, &format!("PM2.5 is {:.0} ug/m3 (threshold {})", pm25, limit));
+        air.pm25_event = true;
+        if config.pm25_fan && !config.dry_run && thermostat.has_fan_circulate() {
+            /* a VOC spike may have paused the fan already; the mode from before it is kept */
+            let prev = air.saved_fan_circulate.unwrap_or_else(|| thermostat.get_fan_circulate());
+            match thermostat.set_fan_circulate(1).await {
+                Ok(_) => air.saved_fan_circulate = Some(prev),
+                Err(e) => print_event(&EventLog {
+                    endpoint: Some("daikin"),
+                    http_status: e.http_status(),
+                    ..EventLog::warning(format!("Failed to turn on fan circulation: {}", e))
+                }),
+            }
+        }
+    } else if air.pm25_event && pm25 < limit * PM25_CLEAR_RATIO {
+        print_event(&EventLog::info(format!("PM2.5 is back to {:.0} ug/m3", pm25)));
+        air.pm25_event = false;
+        if let Some(prev) = air.saved_fan_circulate {
+            /* a VOC spike still going on takes the fan over, and restores it when it ends */
+            let voc_paused = air.voc_spike && config.voc_pause_fan;
+            match thermostat.set_fan_circulate(if voc_paused { 0 } else { prev }).await {
+                Ok(_) if !voc_paused => air.saved_fan_circulate = None,
+                Ok(_) => (),
+                Err(e) => print_event(&EventLog {
+                    endpoint: Some("daikin"),
+                    http_status: e.http_status(),
+                    ..EventLog::warning(format!("Failed to restore fan circulation: {}", e))
+                }),
+            }
+        }
+    }
+}
+
+/**
+ * returns true if `voc` is a spike over `baseline` for the given sensitivity ratio
+ */
+fn is_voc_spike(voc: f64, baseline: f64, ratio: f64) -> bool {
+    voc >= baseline * ratio && voc - baseline >= VOC_SPIKE_MIN
+}
+
+/**
+ * detects VOC spikes, emitting an event and pausing fan circulation as configured
+ */
+pub(crate) async fn check_voc(config: &Config, air: &mut AirState, thermostat: &mut dyn Thermostat, voc: f64) {
+    let ratio = match config.voc_spike {
+        Some(r) => r,
+        None => return,
+    };
+    let baseline = *air.voc_baseline.get_or_insert(voc);
+    let spike = is_voc_spike(voc, baseline, ratio);
+    if spike && !air.voc_spike {
+        print_event(&EventLog::warning(format!("VOC spike: {:.0} ppb (baseline {:.0} ppb)", voc, baseline)));
+        if config.voc_pause_fan && !config.dry_run && !air.pm25_event && thermostat.has_fan_circulate() {
+            let prev = air.saved_fan_circulate.unwrap_or_else(|| thermostat.get_fan_circulate());
+            match thermostat.set_fan_circulate(0).await {
+                Ok(_) => air.saved_fan_circulate = Some(prev),
+                Err(e) => print_event(&EventLog {
+                    endpoint: Some("daikin"),
+                    http_status: e.http_status(),
+                    ..EventLog::warning(format!("Failed to pause fan circulation: {}", e))
+                }),
+            }
+        }
+    } else if !spike && air.voc_spike {
+        print_event(&EventLog::info(format!("VOC is back to {:.0} ppb", voc)));
+        /* during a PM2.5 event the fan is its to restore */
+        if let Some(prev) = air.saved_fan_circulate.filter(|_| !air.pm25_event) {
+            match thermostat.set_fan_circulate(prev).await {
+                Ok(_) => air.saved_fan_circulate = None,
+                Err(e) => print_event(&EventLog {
+                    endpoint: Some("daikin"),
+                    http_status: e.http_status(),
+                    ..EventLog::warning(format!("Failed to restore fan circulation: {}", e))
+                }),
+            }
+        }
+    }
+    air.voc_spike = spike;
+    if !spike {
+        air.voc_baseline = Some(baseline + (voc - baseline) * VOC_BASELINE_WEIGHT);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn mold_risk_index() {
+        let mut air = AirState::default();
+        let hour = 60 * 60;
+        air.record_humidity(0, 80.0);
+        air.record_humidity(hour / 4, 80.0);
+        air.record_humidity(hour / 2, 60.0);
+        air.record_humidity(3 * hour / 4, 80.0);
+        /* 15 + 15 minutes above 70% */
+        assert!((air.mold_risk(70.0, 15) - 50.0 / 24.0).abs() < 0.001);
+        /* a long gap counts only up to the control interval */
+        air.record_humidity(10 * hour, 80.0);
+        assert!((air.mold_risk(70.0, 15) - 75.0 / 24.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn voc_spike() {
+        assert!(is_voc_spike(600.0, 200.0, 2.0));
+        assert!(!is_voc_spike(300.0, 200.0, 2.0));
+        /* doubling at a low level is noise */
+        assert!(!is_voc_spike(80.0, 30.0, 2.0));
+    }
+
+    #[test]
+    fn co2_alert() {
+        let mut config = crate::test::test_config();
+        config.co2_alert = Some(1000.0);
+        config.co2_alert_minutes = 30;
+        let mut air = AirState::default();
+        check_co2(&config, &mut air, 1200.0, 0);
+        assert!(!air.co2_alerted);
+        check_co2(&config, &mut air, 1200.0, 30 * 60);
+        assert!(air.co2_alerted);
+        check_co2(&config, &mut air, 800.0, 45 * 60);
+        assert!(!air.co2_alerted);
+        assert!(air.co2_above_since.is_none());
+    }
+
+    /* a thermostat with just fan circulation */
+    struct FanThermostat(u32);
+
+    #[async_trait::async_trait]
+    impl Thermostat for FanThermostat {
+        async fn sync(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+        fn get_temp_indoor(&self) -> f64 {
+            22.0
+        }
+        fn get_temp_outdoor(&self) -> f64 {
+            10.0
+        }
+        fn get_heat_setpoint(&self) -> f64 {
+            20.0
+        }
+        fn get_cool_setpoint(&self) -> f64 {
+            26.0
+        }
+        fn is_away(&self) -> bool {
+            false
+        }
+        async fn set_setpoints(&mut self, _heat: f64, _cool: f64, _duration: u32) -> Result<(), Error> {
+            Ok(())
+        }
+        fn endpoint(&self) -> &'static str {
+            "fan"
+        }
+        fn has_fan_circulate(&self) -> bool {
+            true
+        }
+        fn get_fan_circulate(&self) -> u32 {
+            self.0
+        }
+        async fn set_fan_circulate(&mut self, mode: u32) -> Result<(), Error> {
+            self.0 = mode;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn fan_handover() {
+        let mut config = crate::test::test_config();
+        config.pm25_alert = Some(35.0);
+        config.pm25_fan = true;
+        config.voc_spike = Some(2.0);
+        config.voc_pause_fan = true;
+        let mut t = FanThermostat(2);
+        let mut air = AirState::default();
+        check_voc(&config, &mut air, &mut t, 200.0).await;
+        /* VOC spike, then PM2.5 event over it */
+        check_voc(&config, &mut air, &mut t, 600.0).await;
+        assert_eq!(t.0, 0);
+        check_pm25(&config, &mut air, &mut t, 50.0).await;
+        assert_eq!(t.0, 1);
+        /* the spike ends during the event: the fan stays on */
+        check_voc(&config, &mut air, &mut t, 200.0).await;
+        assert_eq!(t.0, 1);
+        check_pm25(&config, &mut air, &mut t, 10.0).await;
+        assert_eq!(t.0, 2);
+        /* PM2.5 event, then a spike outlasting it */
+        check_pm25(&config, &mut air, &mut t, 50.0).await;
+        check_voc(&config, &mut air, &mut t, 600.0).await;
+        assert_eq!(t.0, 1);
+        check_pm25(&config, &mut air, &mut t, 10.0).await;
+        assert_eq!(t.0, 0);
+        check_voc(&config, &mut air, &mut t, 200.0).await;
+        assert_eq!(t.0, 2);
+        assert!(air.saved_fan_circulate.is_none());
+    }
+}
@@ -0,0 +1,141 @@
+/*
+ * Backtesting policies on recorded history, and the policy parameter sweep of `--tune FILE`.
+ *
+ * History is daikawa's own log output: the TempLog records (one JSON object per line; events
+ * and other lines are skipped), one per control cycle of `control_interval` minutes. A policy
+ * is replayed over them against a crude room model: Daikin's temperature moves toward the
+ * simulated setpoints at HVAC_RATE while outside them, and drifts toward the outdoor
+ * temperature at LEAK_RATE otherwise. The sensor keeps its recorded offset from Daikin.
+ * Results are only good for comparing policies with each other.
+ */
+use serde::Deserialize;
+use super::*;
+
+/* degrees per minute the equipment moves the temperature while heating or cooling */
+const HVAC_RATE: f64 = 0.05;
+/* share of the indoor-outdoor difference lost per minute while idle */
+const LEAK_RATE: f64 = 0.002;
+/* smallest setpoint change counted as a write */
+const WRITE_THRESHOLD: f64 = 0.05;
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct Record {
+    target_temp_heat: f64,
+    target_temp_cool: f64,
+    awair_temp: f64,
+    #[serde(default)]
+    feels_like_temp: Option<f64>,
+    daikin_indoor_temp: f64,
+    daikin_outdoor_temp: f64,
+    current_heat_setpoint: f64,
+    current_cool_setpoint: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Score {
+    /* mean of how far the sensor was outside the targets, in degrees */
+    pub(crate) tracking_error: f64,
+    /* number of setpoint changes written */
+    pub(crate) writes: usize,
+}
+
+/**
+ * reads the TempLog records in `path`
+ */
+pub(crate) fn load(path: &str) -> Result<Vec<Record>, Error> {
+    let buf = std::fs::read_to_string(path).map_err(|e| Error::Config(format!("Failed to read {}: {}", path, e)))?;
+    let records: Vec<Record> = buf.lines().filter_map(|l| serde_json::from_str(l).ok()).collect();
+    if records.is_empty() {
+        return Err(Error::Config(format!("no TempLog records in {}", path)));
+    }
+    Ok(records)
+}
+
+/**
+ * replays `records` through `policy` and scores it
+ */
+pub(crate) fn run(config: &Config, policy: &dyn policy::ControlPolicy, records: &[Record]) -> Result<Score, Error> {
+    let minutes = config.control_interval as f64;
+    let mut state = policy::PolicyState::default();
+    let mut daikin = records[0].daikin_indoor_temp;
+    let mut current = (records[0].current_heat_setpoint, records[0].current_cool_setpoint);
+    let mut error = 0.0;
+    let mut writes = 0;
+    for (n, r) in records.iter().enumerate() {
+        let sensor = r.feels_like_temp.unwrap_or(r.awair_temp) - r.daikin_indoor_temp + daikin;
+        let targets = (r.target_temp_heat, r.target_temp_cool);
+        let inputs = policy::Inputs {
+            sensor_temp: sensor,
+            daikin_temp: daikin,
+            outdoor_temp: r.daikin_outdoor_temp,
+            targets,
+            current,
+            #[cfg(feature = "script")]
+            readings: Readings { temp: r.awair_temp, humid: None, co2: None, pm25: None, voc: None },
+            now: n as i64 * config.control_interval as i64 * 60,
+        };
+        if let policy::Action::Set(sp) = policy.decide(&inputs, &mut state)? {
+            if (sp.0 - current.0).abs() > WRITE_THRESHOLD || (sp.1 - current.1).abs() > WRITE_THRESHOLD {
+                writes += 1;
+                current = sp;
+            }
+        }
+        error += policy::comfort_error(sensor, targets).abs();
+
+        if daikin < current.0 {
+            daikin = (daikin + HVAC_RATE * minutes).min(current.0);
+        } else if daikin > current.1 {
+            daikin = (daikin - HVAC_RATE * minutes).max(current.1);
+        } else {
+            daikin += (r.daikin_outdoor_temp - daikin) * (LEAK_RATE * minutes).min(1.0);
+        }
+    }
+    Ok(Score { tracking_error: error / records.len() as f64, writes })
+}
+
+/**
+ * replays the history in `path` with each candidate of policy::sweep() and prints them
+ * ranked by tracking error, then by writes
+ */
+pub(crate) fn tune(config: &Config, path: &str) -> Result<(), Error> {
+    let records = load(path)?;
+    let mut results = Vec::new();
+    for (name, policy) in policy::sweep() {
+        results.push((name, run(config, policy.as_ref(), &records)?));
+    }
+    results.sort_by(|a, b| a.1.tracking_error.total_cmp(&b.1.tracking_error).then(a.1.writes.cmp(&b.1.writes)));
+    println!("{} cycles of {} minutes from {}", records.len(), config.control_interval, path);
+    println!("{:>4}  {:<36} {:>14} {:>7}", "rank", "policy", "tracking error", "writes");
+    for (i, (name, score)) in results.iter().enumerate() {
+        println!("{:>4}  {:<36} {:>14.3} {:>7}", i + 1, name, score.tracking_error, score.writes);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn backtest() {
+        let config = crate::test::test_config();
+        let record = |awair: f64| Record {
+            target_temp_heat: 21.0,
+            target_temp_cool: 26.0,
+            awair_temp: awair,
+            feels_like_temp: None,
+            daikin_indoor_temp: 20.0,
+            daikin_outdoor_temp: 0.0,
+            current_heat_setpoint: 20.0,
+            current_cool_setpoint: 25.0,
+        };
+        /* the sensor reads 0.3 degrees either side of Daikin, alternately */
+        let records: Vec<Record> = (0..20).map(|n| record(if n % 2 == 0 { 20.3 } else { 19.7 })).collect();
+        let offset = run(&config, policy::select("offset", &config).as_ref(), &records).unwrap();
+        let mut wide = crate::test::test_config();
+        wide.policy_deadband = 1.0;
+        let deadband = run(&wide, policy::select("deadband", &wide).as_ref(), &records).unwrap();
+        assert!(deadband.writes < offset.writes);
+        assert!(offset.tracking_error < 1.0);
+    }
+}
@@ -0,0 +1,111 @@
+/*
+ * API benchmark (`--bench MINUTES`): the requests a control cycle makes (Awair readings and
+ * Daikin sync) are timed every BENCH_INTERVAL for MINUTES, and their latency percentiles and
+ * error rates printed, to tell a slow cloud from a slow network. The first home is used.
+ *
+ * Mind Awair's quota of `latest` calls (300 a day for hobbyist tokens): each minute of
+ * benchmarking uses 60 / BENCH_INTERVAL of them. A backend left out of the build (feature
+ * "awair" or "daikin-skyport") is not timed.
+ */
+use std::time::{Duration, Instant};
+use super::*;
+
+const BENCH_INTERVAL: Duration = Duration::from_secs(30);
+
+struct Samples {
+    name: &'static str,
+    /* response times of successful requests, in milliseconds */
+    times: Vec<f64>,
+    errors: usize,
+}
+
+impl Samples {
+    fn new(name: &'static str) -> Samples {
+        Samples { name, times: Vec::new(), errors: 0 }
+    }
+
+    async fn time<T>(&mut self, f: impl std::future::Future<Output = Result<T, Error>>) {
+        let started = Instant::now();
+        match f.await {
+            Ok(_) => self.times.push(started.elapsed().as_secs_f64() * 1000.0),
+            Err(e) => {
+                self.errors += 1;
+                eprintln!("{}: {}", self.name, redact::text(&e.to_string()));
+            },
+        }
+    }
+
+    fn print(&mut self) {
+        self.times.sort_by(f64::total_cmp);
+        let total = self.times.len() + self.errors;
+        let p = |q| percentile(&self.times, q).map_or("-".to_string(), |t| format!("{:.0}", t));
+        println!("{:<8} {:>8} {:>7} {:>6.1}% {:>8} {:>8} {:>8} {:>8}", self.name, total, self.errors,
+            if total > 0 { self.errors as f64 * 100.0 / total as f64 } else { 0.0 },
+            p(50.0), p(90.0), p(99.0), p(100.0));
+    }
+}
+
+/**
+ * returns the `q`th percentile (nearest rank) of sorted `values`
+ */
+fn percentile(values: &[f64], q: f64) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    let rank = ((q / 100.0 * values.len() as f64).ceil() as usize).clamp(1, values.len());
+    Some(values[rank - 1])
+}
+
+pub(crate) async fn run(config: &Config, minutes: u32) -> Result<(), Error> {
+    #[cfg(feature = "awair")]
+    let awair = match config.sensor.as_str() {
+        "awair" => Some(awair::Awair::with_sensors(&awair_sensors(config), None).await?),
+        _ => None,
+    };
+    #[cfg(feature = "daikin-skyport")]
+    let mut skyport = match config.thermostat.as_str() {
+        "daikin" => Some(daikin::SkyPort::with_devices(&config.daikin_email, &config.daikin_password, None, config.daikin_device.as_deref()).await?),
+        _ => None,
+    };
+    let mut awair_samples = Samples::new("awair");
+    let mut daikin_samples = Samples::new("daikin");
+    let end = Instant::now() + Duration::from_secs(minutes as u64 * 60);
+    println!("Benchmarking for {} minute(s), one round every {} seconds", minutes, BENCH_INTERVAL.as_secs());
+    loop {
+        let round = Instant::now();
+        #[cfg(feature = "awair")]
+        if let Some(awair) = &awair {
+            awair_samples.time(awair.get_readings()).await;
+        }
+        #[cfg(feature = "daikin-skyport")]
+        if let Some(skyport) = skyport.as_mut() {
+            daikin_samples.time(skyport.sync()).await;
+        }
+        if round + BENCH_INTERVAL >= end {
+            break;
+        }
+        tokio::time::sleep(BENCH_INTERVAL.saturating_sub(round.elapsed())).await;
+    }
+    println!("{:<8} {:>8} {:>7} {:>7} {:>8} {:>8} {:>8} {:>8}", "endpoint", "requests", "errors", "rate", "p50 ms", "p90 ms", "p99 ms", "max ms");
+    for samples in [&mut awair_samples, &mut daikin_samples] {
+        if samples.times.len() + samples.errors > 0 {
+            samples.print();
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn percentiles() {
+        let values: Vec<f64> = (1..=10).map(|v| v as f64 * 10.0).collect();
+        assert_eq!(percentile(&values, 50.0), Some(50.0));
+        assert_eq!(percentile(&values, 90.0), Some(90.0));
+        assert_eq!(percentile(&values, 99.0), Some(100.0));
+        assert_eq!(percentile(&values, 0.0), Some(10.0));
+        assert_eq!(percentile(&[], 50.0), None);
+    }
+}
@@ -0,0 +1,80 @@
+/*
+ * Daily HVAC runtime budget for daikawa's overrides.
+ *
+ * Runtime is estimated from samples taken at every control cycle: when Daikin reports
+ * heating or cooling while daikawa's setpoints are in effect, the time since the previous
+ * sample is counted. Once the day's total reaches `daily_runtime_budget` minutes, control
+ * stops until the next day and Daikin One+ follows its native schedule.
+ */
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct RuntimeBudget {
+    /* local date ("%Y-%m-%d") the runtime is counted for */
+    day: Option<String>,
+    /* estimated runtime in minutes */
+    minutes: f64,
+    /* unix time of the previous sample */
+    sampled_at: Option<i64>,
+    exhausted: bool,
+}
+
+impl RuntimeBudget {
+    /**
+     * accumulates runtime since the previous sample. Gaps longer than `max_gap` minutes
+     * (e.g. outside the control window) are counted as `max_gap` at most.
+     */
+    pub(crate) fn record(&mut self, today: &str, now: i64, running: bool, overriding: bool, max_gap: u32) {
+        if self.day.as_deref() != Some(today) {
+            *self = RuntimeBudget { day: Some(today.to_string()), ..RuntimeBudget::default() };
+        }
+        if let Some(prev) = self.sampled_at {
+            if running && overriding {
+                let gap = (now - prev).max(0) as f64 / 60.0;
+                self.minutes += gap.min(max_gap as f64);
+            }
+        }
+        self.sampled_at = Some(now);
+    }
+
+    pub(crate) fn minutes(&self) -> f64 {
+        self.minutes
+    }
+
+    /**
+     * returns true if the budget is used up. `newly` is set the first time it happens on a day.
+     */
+    pub(crate) fn check(&mut self, budget: u32) -> (bool, bool) {
+        if self.minutes < budget as f64 {
+            return (false, false);
+        }
+        let newly = !self.exhausted;
+        self.exhausted = true;
+        (true, newly)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn budget_per_day() {
+        let mut b = RuntimeBudget::default();
+        b.record("2022-03-18", 0, true, true, 15);
+        assert_eq!(b.minutes(), 0.0);
+        b.record("2022-03-18", 15 * 60, true, true, 15);
+        b.record("2022-03-18", 30 * 60, false, true, 15);
+        b.record("2022-03-18", 45 * 60, true, false, 15);
+        assert_eq!(b.minutes(), 15.0);
+        /* a long gap is capped */
+        b.record("2022-03-18", 10 * 60 * 60, true, true, 15);
+        assert_eq!(b.minutes(), 30.0);
+        assert_eq!(b.check(30), (true, true));
+        assert_eq!(b.check(30), (true, false));
+        /* a new day starts over */
+        b.record("2022-03-19", 11 * 60 * 60, true, true, 15);
+        assert_eq!(b.minutes(), 0.0);
+        assert_eq!(b.check(30), (false, false));
+    }
+}
@@ -0,0 +1,112 @@
+/*
+ * Chart of the last 24 hours (`--chart FILE`, feature "chart"): the controlled temperature
+ * and the Daikin setpoints of the TempLog records in FILE (daikawa's own log, as replayed by
+ * `--tune`), drawn as SVG on standard output. Records are placed by their `timestamp`, and
+ * the last 24 hours end at the latest of them; records without one are skipped.
+ */
+use plotters::prelude::*;
+use serde::Deserialize;
+use super::*;
+
+const WIDTH: u32 = 960;
+const HEIGHT: u32 = 480;
+const HOURS: f64 = 24.0;
+
+#[derive(Debug, Deserialize)]
+struct Record {
+    awair_temp: f64,
+    #[serde(default)]
+    feels_like_temp: Option<f64>,
+    current_heat_setpoint: f64,
+    current_cool_setpoint: f64,
+}
+
+/* (hours before the last record, value) of each record */
+type Series = Vec<(f64, f64)>;
+
+/**
+ * returns room temperature, heat setpoint and cool setpoint series of the records made in
+ * the last `HOURS` before the latest of `records`, given as (unix time, record)
+ */
+fn last_day(records: &[(i64, Record)]) -> (Series, Series, Series) {
+    let end = records.iter().map(|(t, _)| *t).max().unwrap_or(0);
+    let recent: Vec<(f64, &Record)> = records.iter()
+        .map(|(t, r)| ((t - end) as f64 / 3600.0, r))
+        .filter(|(h, _)| *h >= -HOURS)
+        .collect();
+    let series = |f: &dyn Fn(&Record) -> f64| recent.iter().map(|(h, r)| (*h, f(r))).collect::<Series>();
+    (series(&|r| r.feels_like_temp.unwrap_or(r.awair_temp)),
+     series(&|r| r.current_heat_setpoint),
+     series(&|r| r.current_cool_setpoint))
+}
+
+fn draw_error<E: std::fmt::Display>(e: E) -> Error {
+    Error::Io(std::io::Error::other(e.to_string()))
+}
+
+/**
+ * returns the SVG chart of the TempLog records in `path`
+ */
+pub(crate) fn render(config: &Config, path: &str) -> Result<String, Error> {
+    let buf = std::fs::read_to_string(path).map_err(|e| Error::Config(format!("Failed to read {}: {}", path, e)))?;
+    let records: Vec<(i64, Record)> = buf.lines()
+        .filter_map(|l| serde_json::from_str::<serde_json::Value>(l).ok())
+        .filter_map(|v| Some((sink::timestamp(&v)?, serde_json::from_value(v).ok()?)))
+        .collect();
+    if records.is_empty() {
+        return Err(Error::Config(format!("no TempLog records in {}", path)));
+    }
+    let (room, heat, cool) = last_day(&records);
+    let values = room.iter().chain(heat.iter()).chain(cool.iter()).map(|(_, v)| *v);
+    let low = values.clone().fold(f64::INFINITY, f64::min).floor() - 1.0;
+    let high = values.fold(f64::NEG_INFINITY, f64::max).ceil() + 1.0;
+
+    let mut svg = String::new();
+    {
+        let root = SVGBackend::with_string(&mut svg, (WIDTH, HEIGHT)).into_drawing_area();
+        root.fill(&WHITE).map_err(draw_error)?;
+        let title = match &config.name {
+            Some(name) => format!("{}: last 24 hours", name),
+            None => "last 24 hours".to_string(),
+        };
+        let mut chart = ChartBuilder::on(&root)
+            .caption(title, ("sans-serif", 20))
+            .margin(10)
+            .x_label_area_size(30)
+            .y_label_area_size(40)
+            .build_cartesian_2d(-HOURS..0.0, low..high)
+            .map_err(draw_error)?;
+        chart.configure_mesh().x_desc("hours").y_desc("temperature").draw().map_err(draw_error)?;
+        for (series, color, label) in [(room, BLACK, "room"), (heat, RED, "heat setpoint"), (cool, BLUE, "cool setpoint")] {
+            chart.draw_series(LineSeries::new(series, &color)).map_err(draw_error)?
+                .label(label)
+                .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], color));
+        }
+        chart.configure_series_labels().background_style(WHITE.mix(0.8)).border_style(BLACK).draw().map_err(draw_error)?;
+        root.present().map_err(draw_error)?;
+    }
+    Ok(svg)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn last_24_hours() {
+        let record = |t: f64| Record { awair_temp: t, feels_like_temp: None, current_heat_setpoint: 20.0, current_cool_setpoint: 25.0 };
+        /* 30 hours of 10 minute cycles */
+        let records: Vec<(i64, Record)> = (0..180).map(|n| (n * 600, record(n as f64))).collect();
+        let (room, heat, cool) = last_day(&records);
+        assert_eq!(room.len(), 145);
+        assert_eq!(room[0], (-24.0, 35.0));
+        assert_eq!(room[144], (0.0, 179.0));
+        assert_eq!(heat.len(), cool.len());
+        let (room, _, _) = last_day(&records[..3]);
+        assert_eq!(room, vec![(-1.0 / 3.0, 0.0), (-1.0 / 6.0, 1.0), (0.0, 2.0)]);
+        /* a gap (e.g. outside the control window) is a gap in time, not in cycles */
+        let records = vec![(0, record(1.0)), (20 * 3600, record(2.0)), (30 * 3600, record(3.0))];
+        let (room, _, _) = last_day(&records);
+        assert_eq!(room, vec![(-10.0, 2.0), (0.0, 3.0)]);
+    }
+}
@@ -0,0 +1,309 @@
+/*
+ * Runtime commands (from MQTT or the command socket), given as JSON objects:
+ *
+ *   {"command": "set_targets", "heat": 21.0, "cool": 26.0}   replace targets until cleared
+ *   {"command": "set_targets"}                                 clear replaced targets
+ *   {"command": "pause", "minutes": 120}                      stop changing setpoints (still reading and logging),
+ *                                                              resuming by itself after minutes if given
+ *   {"command": "resume"}                                      apply control again
+ *   {"command": "hold"}                                        keep Daikin One+'s current setpoints (e.g. set by hand),
+ *                                                              renewing the override every cycle without correcting them
+ *   {"command": "release"}                                     end a hold
+ *   {"command": "boost", "degrees": 1.0, "minutes": 60}       raise heating/lower cooling targets for a while
+ *   {"command": "preset", "name": "weekend"}                  use a profile's targets regardless of day
+ *   {"command": "preset"}                                      back to the day's profile
+ *   {"command": "set_mode", "mode": "heat"}                   adjust only heating ("auto", "heat", "cool" or "season")
+ *   {"command": "set_mode"}                                    back to the configured mode
+ *
+ * An optional "home" key addresses one of several homes; without it all homes receive the command.
+ * Overrides are kept in the control state, so they survive restarts.
+ */
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::UnboundedSender;
+use super::*;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Command {
+    SetTargets {
+        heat: Option<f64>,
+        cool: Option<f64>,
+    },
+    Pause {
+        minutes: Option<u32>,
+    },
+    Resume,
+    Hold,
+    Release,
+    Boost {
+        #[serde(default = "default_boost_degrees")]
+        degrees: f64,
+        #[serde(default = "default_boost_minutes")]
+        minutes: u32,
+    },
+    Preset {
+        name: Option<String>,
+    },
+    SetMode {
+        mode: Option<String>,
+    },
+}
+
+fn default_boost_degrees() -> f64 {
+    1.0
+}
+
+fn default_boost_minutes() -> u32 {
+    60
+}
+
+/* a command with the home it is for */
+#[derive(Debug, Deserialize)]
+struct Envelope {
+    home: Option<String>,
+    #[serde(flatten)]
+    command: Command,
+}
+
+/**
+ * parses a command message; returns the home it is addressed to (if any) and the command
+ */
+pub fn parse(message: &[u8]) -> Result<(Option<String>, Command), Error> {
+    let e: Envelope = serde_json::from_slice(message)?;
+    Ok((e.home, e.command))
+}
+
+/**
+ * parses a command message and forwards it to the controllers of the homes it is addressed to
+ */
+pub fn dispatch(homes: &[(Option<String>, UnboundedSender<controller::Event>)], message: &[u8]) -> Result<(), String> {
+    let (home, cmd) = parse(message).map_err(|e| format!("malformed command: {}", e))?;
+    let mut delivered = false;
+    for (_, tx) in homes.iter().filter(|(name, _)| home.is_none() || *name == home) {
+        delivered |= tx.send(controller::Event::Command(cmd.clone())).is_ok();
+    }
+    if !delivered {
+        return Err(format!("unknown home {:?}", home));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Overrides {
+    /* targets replaced by set_targets */
+    heat: Option<f64>,
+    cool: Option<f64>,
+    pub paused: bool,
+    /* unix time a pause with a timeout ends */
+    #[serde(default)]
+    pause_until: Option<i64>,
+    /* (unix time the boost ends, degrees) */
+    boost: Option<(i64, f64)>,
+    preset: Option<String>,
+    /* (heat, cool) setpoints frozen by hold */
+    #[serde(default)]
+    pub hold: Option<(f64, f64)>,
+    /* replaces the configured `mode` */
+    #[serde(default)]
+    pub(crate) mode: Option<String>,
+}
+
+impl Overrides {
+    /**
+     * applies a command, returning a description for the log. `current` is Daikin's
+     * current (heat, cool) setpoints.
+     */
+    pub(crate) fn apply(&mut self, config: &Config, command: Command, current: (f64, f64), now: i64) -> Result<String, String> {
+        match command {
+            Command::SetTargets { heat, cool } => {
+                for t in heat.iter().chain(cool.iter()) {
+                    if !(MIN_TARGET_TEMP..=MAX_TARGET_TEMP).contains(t) {
+                        return Err(format!("target {} is out of the sane range ({} - {})", t, MIN_TARGET_TEMP, MAX_TARGET_TEMP));
+                    }
+                }
+                if let (Some(h), Some(c)) = (heat, cool) {
+                    if h + MIN_TARGET_GAP > c {
+                        return Err(format!("heat target {} must be at least {} degrees below cool target {}", h, MIN_TARGET_GAP, c));
+                    }
+                }
+                self.heat = heat;
+                self.cool = cool;
+                Ok(format!("targets set to heat={:?}, cool={:?}", heat, cool))
+            },
+            Command::Pause { minutes } => {
+                self.paused = true;
+                self.pause_until = minutes.map(|m| now + m as i64 * 60);
+                match minutes {
+                    Some(m) => Ok(format!("control paused for {} minutes", m)),
+                    None => Ok("control paused".to_string()),
+                }
+            },
+            Command::Resume => {
+                self.paused = false;
+                self.pause_until = None;
+                Ok("control resumed".to_string())
+            },
+            Command::Hold => {
+                self.hold = Some(current);
+                Ok(format!("holding setpoints heat={:.1}, cool={:.1}", current.0, current.1))
+            },
+            Command::Release => {
+                self.hold = None;
+                Ok("hold released".to_string())
+            },
+            Command::Boost { degrees, minutes } => {
+                if degrees <= 0.0 || degrees > MAX_SETBACK {
+                    return Err(format!("boost must be between 0 and {} degrees", MAX_SETBACK));
+                }
+                self.boost = Some((now + minutes as i64 * 60, degrees));
+                Ok(format!("boosting by {} degrees for {} minutes", degrees, minutes))
+            },
+            Command::Preset { name } => {
+                if let Some(n) = &name {
+                    if !config.profiles.contains_key(n) {
+                        return Err(format!("profile \"{}\" is not defined", n));
+                    }
+                }
+                let msg = format!("preset set to {:?}", name);
+                self.preset = name;
+                Ok(msg)
+            },
+            Command::SetMode { mode } => {
+                if let Some(m) = &mode {
+                    if parse_control_mode(m).is_none() {
+                        return Err(format!("mode \"{}\" is not one of auto, heat, cool or season", m));
+                    }
+                    if m == "season" && config.thermostat == "exec" {
+                        return Err("mode \"season\" needs the outdoor temperature, which thermostat = \"exec\" does not provide".to_string());
+                    }
+                }
+                let msg = match &mode {
+                    Some(m) => format!("mode set to {}", m),
+                    None => format!("mode back to {}", config.mode),
+                };
+                self.mode = mode;
+                Ok(msg)
+            },
+        }
+    }
+
+    /**
+     * ends a pause whose timeout has passed at `now`; returns true if it did
+     */
+    pub(crate) fn expire_pause(&mut self, now: i64) -> bool {
+        match self.pause_until {
+            Some(until) if self.paused && now >= until => {
+                self.paused = false;
+                self.pause_until = None;
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /**
+     * returns targets adjusted by the overrides in effect at `now`. Overridden targets are kept
+     * within the sane range and MIN_TARGET_GAP apart; when only one side was replaced, the
+     * other side makes room for it.
+     */
+    pub(crate) fn targets(&self, config: &Config, (mut heat, mut cool): (f64, f64), now: i64) -> (f64, f64) {
+        let preset = self.preset.as_ref().and_then(|n| config.profiles.get(n));
+        if let Some(p) = preset {
+            heat = p.target_temp_heat.unwrap_or(heat);
+            cool = p.target_temp_cool.unwrap_or(cool);
+        }
+        heat = self.heat.unwrap_or(heat);
+        cool = self.cool.unwrap_or(cool);
+        let boosted = match self.boost {
+            Some((until, degrees)) if now < until => {
+                heat += degrees;
+                cool -= degrees;
+                true
+            },
+            _ => false,
+        };
+        if preset.is_none() && self.heat.is_none() && self.cool.is_none() && !boosted {
+            return (heat, cool);
+        }
+        if heat + MIN_TARGET_GAP > cool {
+            match (self.heat.is_some(), self.cool.is_some()) {
+                (true, false) => cool = heat + MIN_TARGET_GAP,
+                (false, true) => heat = cool - MIN_TARGET_GAP,
+                _ => {
+                    let middle = (heat + cool) / 2.0;
+                    heat = middle - MIN_TARGET_GAP / 2.0;
+                    cool = middle + MIN_TARGET_GAP / 2.0;
+                },
+            }
+        }
+        cool = cool.clamp(MIN_TARGET_TEMP + MIN_TARGET_GAP, MAX_TARGET_TEMP);
+        heat = heat.clamp(MIN_TARGET_TEMP, cool - MIN_TARGET_GAP);
+        (heat, cool)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn commands() {
+        let (home, cmd) = parse(br#"{"command": "boost", "home": "cabin"}"#).unwrap();
+        assert_eq!(home.as_deref(), Some("cabin"));
+        assert_eq!(cmd, Command::Boost { degrees: 1.0, minutes: 60 });
+        assert!(parse(br#"{"command": "explode"}"#).is_err());
+
+        let config = crate::test::test_config();
+        let mut o = Overrides::default();
+        o.apply(&config, parse(br#"{"command": "set_targets", "heat": 22.0}"#).unwrap().1, (20.0, 26.0), 0).unwrap();
+        o.apply(&config, cmd, (20.0, 26.0), 0).unwrap();
+        assert_eq!(o.targets(&config, (20.0, 26.0), 30 * 60), (23.0, 25.0));
+        /* the boost is over, the replaced target stays */
+        assert_eq!(o.targets(&config, (20.0, 26.0), 60 * 60), (22.0, 26.0));
+        assert!(o.apply(&config, Command::Preset { name: Some("nonexistent".to_string()) }, (20.0, 26.0), 0).is_err());
+
+        o.apply(&config, parse(br#"{"command": "pause", "minutes": 30}"#).unwrap().1, (20.0, 26.0), 0).unwrap();
+        assert!(!o.expire_pause(29 * 60) && o.paused);
+        assert!(o.expire_pause(30 * 60) && !o.paused);
+        o.apply(&config, parse(br#"{"command": "pause"}"#).unwrap().1, (20.0, 26.0), 0).unwrap();
+        assert!(!o.expire_pause(24 * 60 * 60) && o.paused);
+
+        /* out of range, or too close together */
+        let set = |heat, cool| Command::SetTargets { heat, cool };
+        assert!(o.apply(&config, set(Some(40.0), None), (20.0, 26.0), 0).is_err());
+        assert!(o.apply(&config, set(None, Some(5.0)), (20.0, 26.0), 0).is_err());
+        assert!(o.apply(&config, set(Some(24.0), Some(23.0)), (20.0, 26.0), 0).is_err());
+        assert!(o.apply(&config, set(Some(24.0), Some(24.5)), (20.0, 26.0), 0).is_err());
+        /* one-sided: the other target makes room */
+        let mut o = Overrides::default();
+        o.apply(&config, set(Some(27.0), None), (20.0, 26.0), 0).unwrap();
+        assert_eq!(o.targets(&config, (20.0, 26.0), 0), (27.0, 28.0));
+        o.apply(&config, set(Some(32.0), None), (20.0, 26.0), 0).unwrap();
+        assert_eq!(o.targets(&config, (20.0, 26.0), 0), (31.0, 32.0));
+        o.apply(&config, set(None, Some(19.0)), (20.0, 26.0), 0).unwrap();
+        assert_eq!(o.targets(&config, (20.0, 26.0), 0), (18.0, 19.0));
+        /* a boost that inverts the targets meets in the middle */
+        let mut o = Overrides::default();
+        o.apply(&config, Command::Boost { degrees: 2.0, minutes: 60 }, (20.0, 26.0), 0).unwrap();
+        assert_eq!(o.targets(&config, (22.0, 24.0), 0), (22.5, 23.5));
+        /* without overrides targets are left alone */
+        assert_eq!(Overrides::default().targets(&config, (22.0, 22.0), 0), (22.0, 22.0));
+
+        /* kept in the control state across restarts */
+        let (_, cmd) = parse(br#"{"command": "set_mode", "mode": "cool"}"#).unwrap();
+        assert_eq!(cmd, Command::SetMode { mode: Some("cool".to_string()) });
+        let mut o = Overrides::default();
+        o.apply(&config, cmd, (20.0, 26.0), 0).unwrap();
+        let restored: Overrides = serde_json::from_str(&serde_json::to_string(&o).unwrap()).unwrap();
+        assert_eq!(restored.mode.as_deref(), Some("cool"));
+        assert!(o.apply(&config, Command::SetMode { mode: Some("fan".to_string()) }, (20.0, 26.0), 0).is_err());
+        o.apply(&config, Command::SetMode { mode: None }, (20.0, 26.0), 0).unwrap();
+        assert_eq!(o.mode, None);
+
+        let mut o = Overrides::default();
+        o.apply(&config, Command::Hold, (21.5, 25.5), 0).unwrap();
+        assert_eq!(o.hold, Some((21.5, 25.5)));
+        o.apply(&config, Command::Release, (22.0, 26.0), 0).unwrap();
+        assert_eq!(o.hold, None);
+    }
+}
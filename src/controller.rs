@@ -0,0 +1,273 @@
+/*
+ * The control loop as an explicit state machine.
+ *
+ * Idle --(window starts)--> Controlling --(window ends)--> Idle
+ * Controlling <--> Holding   (Awair unavailable, last setpoints re-applied)
+ * Controlling <--> Degraded  (an API keeps failing, Daikin One+ follows its own schedule)
+ * any --(SIGINT/SIGTERM)--> ShuttingDown
+ *
+ * Entry/exit actions (quiet mode, state file) are tied to transitions, and the
+ * loop waits for either the next timer tick or an external event.
+ */
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::UnboundedReceiver;
+use super::*;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub enum Phase {
+    #[default]
+    Idle,
+    Controlling,
+    Degraded,
+    Holding,
+    ShuttingDown,
+}
+
+impl Phase {
+    /* true if the phase belongs to the control window */
+    fn in_window(&self) -> bool {
+        matches!(self, Phase::Controlling | Phase::Degraded | Phase::Holding)
+    }
+}
+
+/* events delivered to the controller from outside the control loop */
+pub enum Event {
+    Shutdown,
+    Command(command::Command),
+}
+
+pub(crate) struct Controller<'a> {
+    config: &'a Config,
+    sensor: Box<dyn TemperatureSensor + Send + 'a>,
+    thermostat: Box<dyn Thermostat + Send>,
+    state: ControlState,
+    /* devices last discovered, for the device cache */
+    devices: devcache::DeviceCache,
+    retry_state: RetryState,
+    circuits: Circuits,
+    /* when devices were last discovered */
+    last_discovery: std::time::Instant,
+}
+
+impl<'a> Controller<'a> {
+    pub(crate) fn new(config: &'a Config, home: Home<'a>) -> Controller<'a> {
+        let Home { sensor, thermostat, mut state, .. } = home;
+        if state.phase == Phase::ShuttingDown {
+            /*
+             * shut down gracefully last time, so window entry actions were already undone; night
+             * mode settings a failed restore left in the state are restored when the next window ends
+             */
+            state.phase = Phase::Idle;
+        }
+        Controller {
+            config,
+            sensor,
+            thermostat,
+            state,
+            devices: home.devices,
+            retry_state: RetryState::default(),
+            circuits: Circuits::new(),
+            last_discovery: std::time::Instant::now(),
+        }
+    }
+
+    /**
+     * discovers devices again, follows the configured thermostat device and reports changes
+     */
+    async fn rediscover(&mut self) {
+        self.last_discovery = std::time::Instant::now();
+        match self.thermostat.rediscover(self.config.daikin_device.as_deref(), &mut self.devices).await {
+            Ok(changes) => {
+                for c in changes.iter() {
+                    print_event(&EventLog { endpoint: Some(self.thermostat.endpoint()), ..EventLog::info(c.clone()) });
+                }
+                if !changes.is_empty() {
+                    save_device_cache(self.config, &self.devices, None);
+                }
+            },
+            Err(e) => print_event(&EventLog {
+                endpoint: Some(self.thermostat.endpoint()),
+                http_status: e.http_status(),
+                ..EventLog::warning(format!("Thermostat device discovery failed: {}", e))
+            }),
+        }
+        match self.sensor.check_devices().await {
+            Ok(missing) => for m in missing.into_iter() {
+                print_event(&EventLog { endpoint: Some(self.sensor.endpoint()), ..EventLog::warning(m) });
+            },
+            Err(e) => print_event(&EventLog {
+                endpoint: Some(self.sensor.endpoint()),
+                http_status: e.http_status(),
+                ..EventLog::warning(format!("Sensor device discovery failed: {}", e))
+            }),
+        }
+    }
+
+    /* true if devices should be discovered again after `result` */
+    fn needs_rediscovery(&self, result: &Result<(), ControlError>) -> bool {
+        if let Err(e) = result {
+            /* the device may have been replaced or removed */
+            if matches!(e.error, Error::Http { status: 404, .. }) {
+                return true;
+            }
+        }
+        self.config.rediscover_interval > 0
+            && self.last_discovery.elapsed().as_secs() >= self.config.rediscover_interval as u64 * 60 * 60
+    }
+
+    async fn transition(&mut self, to: Phase) {
+        let from = self.state.phase;
+        if from == to {
+            return;
+        }
+        print_event(&EventLog::info(format!("state transition: {:?} -> {:?}", from, to)));
+        if !from.in_window() && to.in_window() {
+            apply_quiet_mode(self.thermostat.as_mut(), self.config, true, &mut self.state.saved_night_mode).await;
+            self.state.ramp_from = None;
+        } else if from.in_window() && !to.in_window() {
+            apply_quiet_mode(self.thermostat.as_mut(), self.config, false, &mut self.state.saved_night_mode).await;
+            self.state.ramp_from = None;
+        }
+        self.state.phase = to;
+    }
+
+    /**
+     * runs one timer tick and returns minutes until the next one, or None if a shutdown
+     * cancelled the cycle's requests
+     */
+    async fn tick(&mut self) -> Option<u32> {
+        let in_window = profile::active_settings(self.config, &wall_clock(self.config)).is_some();
+        if self.state.overrides.expire_pause(chrono::Local::now().timestamp()) {
+            print_event(&EventLog::info("command: pause timed out, control resumed".to_string()));
+        }
+        if !in_window {
+            self.transition(Phase::Idle).await;
+            return Some(24*60); /* sleep forever */
+        }
+        if self.state.phase == Phase::Idle {
+            self.transition(Phase::Controlling).await;
+        }
+
+        if let Some(wait) = self.circuits.wait_minutes(std::time::Instant::now()) {
+            /* an API is failing, leave Daikin One+ on its own schedule */
+            self.transition(Phase::Degraded).await;
+            return Some(wait);
+        }
+
+        let result = {
+            let _slot = throttle::acquire().await;
+            do_control(self.sensor.as_ref(), self.thermostat.as_mut(), self.config, &mut self.state).await
+        };
+        if matches!(&result, Err(e) if matches!(e.error, Error::Cancelled)) {
+            /* not a failure of the APIs, and holding setpoints now would outlast the shutdown */
+            return None;
+        }
+        self.circuits.record(&result, self.thermostat.endpoint(), self.config);
+        online::update(self.config, &mut self.state.offline, result.as_ref().err().map(|e| &e.error),
+            chrono::Local::now().timestamp());
+        if self.needs_rediscovery(&result) {
+            self.rediscover().await;
+        }
+        let interval = self.retry_state.next_interval(result.is_ok(), self.config);
+        let next = match &result {
+            Ok(_) => Phase::Controlling,
+            Err(_) if self.circuits.wait_minutes(std::time::Instant::now()).is_some()
+                || self.retry_state.is_degraded(self.config) => Phase::Degraded,
+            Err(e) if e.endpoint != self.thermostat.endpoint() && hold_setpoints(self.thermostat.as_mut(), self.config, &self.state).await => Phase::Holding,
+            Err(_) => self.state.phase,
+        };
+        if let Err(e) = result {
+            e.log(interval);
+        }
+        self.transition(next).await;
+        Some(interval)
+    }
+
+    fn save_state(&self) {
+        if let Some(path) = &self.config.state_file {
+            if let Err(e) = self.state.save(path) {
+                print_event(&EventLog::warning(format!("Failed to write state file {}", e)));
+            }
+        }
+    }
+
+    async fn command(&mut self, cmd: command::Command) {
+        if cmd == command::Command::Hold {
+            /* hold what the thermostat has now, including changes made by hand since the last cycle */
+            if let Err(e) = self.thermostat.sync().await {
+                print_event(&EventLog {
+                    endpoint: Some(self.thermostat.endpoint()),
+                    http_status: e.http_status(),
+                    ..EventLog::warning(format!("command rejected: thermostat sync failed: {}", e))
+                });
+                return;
+            }
+        }
+        let current = (self.thermostat.get_heat_setpoint(), self.thermostat.get_cool_setpoint());
+        match self.state.overrides.apply(self.config, cmd, current, chrono::Local::now().timestamp()) {
+            Ok(msg) => print_event(&EventLog::info(format!("command: {}", msg))),
+            Err(msg) => print_event(&EventLog::warning(format!("command rejected: {}", msg))),
+        }
+    }
+
+    fn write_status(&self, next_wake: i64) {
+        if let Some(path) = &self.config.status_file {
+            let now = chrono::Local::now().timestamp();
+            if let Err(e) = status::Status::new(self.config, self.thermostat.as_ref(), &self.state, now, next_wake).save(path) {
+                print_event(&EventLog::warning(format!("Failed to write status file {}", e)));
+            }
+        }
+    }
+
+    async fn shutdown(&mut self) {
+        self.transition(Phase::ShuttingDown).await;
+        self.save_state();
+        self.write_status(chrono::Local::now().timestamp());
+    }
+
+    /**
+     * runs the control loop until a shutdown event is received
+     */
+    pub(crate) async fn run(&mut self, mut events: UnboundedReceiver<Event>) {
+        loop {
+            let next = secs_to_next_transition(self.config) + 15;
+            begin_cycle();
+            let interval_min = self.tick().await;
+            self.save_state();
+            end_cycle();
+            let interval_min = match interval_min {
+                Some(i) => i,
+                None => {
+                    /* requests are only cancelled on shutdown, whose event follows */
+                    while let Some(event) = events.recv().await {
+                        if let Event::Shutdown = event {
+                            break;
+                        }
+                    }
+                    self.shutdown().await;
+                    return;
+                },
+            };
+
+            if self.config.oneshot {
+                return;
+            }
+
+            let sleep_sec = std::cmp::min(std::cmp::min(next, interval_min as i64 * 60), MAX_SLEEP_SEC);
+            self.write_status(chrono::Local::now().timestamp() + sleep_sec);
+            println!("sleeping for {} seconds ({} minutes until next state transition)", sleep_sec, next / 60);
+            let dur = std::time::Duration::from_secs(sleep_sec.try_into().unwrap_or(0));
+            match tokio::time::timeout(dur, events.recv()).await {
+                Ok(Some(Event::Shutdown)) => {
+                    self.shutdown().await;
+                    return;
+                },
+                /* act on the command right away */
+                Ok(Some(Event::Command(cmd))) => self.command(cmd).await,
+                Err(_) => (),
+                /* no event sources left, just keep ticking */
+                Ok(None) => tokio::time::sleep(dur).await,
+            }
+        }
+    }
+}
@@ -0,0 +1,99 @@
+/*
+ * On-disk cache of discovered Awair and Daikin devices, so startup does not need
+ * the device discovery endpoints every time. Each list is kept only by a build with its
+ * backend's feature.
+ */
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "awair")]
+use super::awair;
+#[cfg(feature = "daikin-skyport")]
+use super::daikin;
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct DeviceCache {
+    /* unix time of the discovery */
+    discovered_at: i64,
+    #[cfg(feature = "awair")]
+    #[serde(default)]
+    awair: Vec<awair::Device>,
+    #[cfg(feature = "daikin-skyport")]
+    #[serde(default)]
+    daikin: Vec<daikin::DeviceEntry>,
+}
+
+impl DeviceCache {
+    /**
+     * reads the cache; returns None if it does not exist, cannot be parsed,
+     * or is older than `ttl_hours`
+     */
+    pub fn load(path: &str, ttl_hours: u32, now: i64) -> Option<DeviceCache> {
+        let buf = std::fs::read(path).ok()?;
+        let cache: DeviceCache = serde_json::from_slice(&buf).ok()?;
+        if now - cache.discovered_at > ttl_hours as i64 * 60 * 60 {
+            return None;
+        }
+        Some(cache)
+    }
+
+    /* the device lists depend on the features built */
+    #[allow(clippy::needless_update)]
+    pub fn new(now: i64) -> DeviceCache {
+        DeviceCache { discovered_at: now, ..Default::default() }
+    }
+
+    #[cfg(feature = "awair")]
+    pub fn set_awair(&mut self, devices: &[awair::Device], now: i64) {
+        self.awair = devices.to_vec();
+        self.discovered_at = now;
+    }
+
+    #[cfg(feature = "daikin-skyport")]
+    pub fn set_daikin(&mut self, devices: &[daikin::DeviceEntry], now: i64) {
+        self.daikin = devices.to_vec();
+        self.discovered_at = now;
+    }
+
+    #[cfg(feature = "awair")]
+    pub fn awair_devices(&self) -> Vec<awair::Device> {
+        self.awair.clone()
+    }
+
+    #[cfg(feature = "daikin-skyport")]
+    pub fn daikin_devices(&self) -> Vec<daikin::DeviceEntry> {
+        self.daikin.clone()
+    }
+
+    /* true if both hold the same devices, whenever discovered */
+    #[allow(clippy::needless_update)]
+    pub fn is_same(&self, other: &DeviceCache) -> bool {
+        let devices = |c: &DeviceCache| serde_json::to_value(DeviceCache { discovered_at: 0, ..c.clone() }).ok();
+        devices(self) == devices(other)
+    }
+
+    /**
+     * writes the cache atomically
+     */
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let json = serde_json::to_vec_pretty(self).map_err(|e| e.to_string())?;
+        let tmp = format!("{}.tmp", path);
+        std::fs::write(&tmp, json).map_err(|e| format!("{}: {}", tmp, e))?;
+        std::fs::rename(&tmp, path).map_err(|e| format!("{}: {}", path, e))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn expiry() {
+        let path = std::env::temp_dir().join(format!("daikawa-devcache-test-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        let cache = DeviceCache::new(1000);
+        cache.save(path).unwrap();
+        assert!(DeviceCache::load(path, 1, 1000 + 60 * 60).is_some());
+        assert!(DeviceCache::load(path, 1, 1000 + 60 * 60 + 1).is_none());
+        std::fs::remove_file(path).unwrap();
+        assert!(DeviceCache::load(path, 1, 1000).is_none());
+    }
+}
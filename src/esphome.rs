@@ -0,0 +1,93 @@
+/*
+ * ESPHome nodes (`sensor = "esphome"`), e.g. a DIY ESP32 temperature probe, read over the REST
+ * API of their web_server component:
+ *
+ *   GET http://<esphome_host>/sensor/<object id>
+ *   {"id": "sensor-bedroom_temperature", "value": 21.3, "state": "21.3 °C"}
+ *
+ * `esphome_sensor` is the sensor's name or object id (its name in snake case), and
+ * `esphome_humidity_sensor` an optional humidity sensor on the same node. A sensor without a
+ * value yet (`"value": null`, or the non-JSON `NaN` some versions send, with state "NA") is
+ * reported offline.
+ */
+use serde::Deserialize;
+use super::*;
+
+#[derive(Debug, Deserialize)]
+struct SensorState {
+    value: Option<f64>,
+}
+
+/**
+ * returns the object id ESPHome derives from a sensor name, e.g. "Bedroom Temperature" ->
+ * "bedroom_temperature". Like ESPHome's str_sanitize, every other byte (so each byte of a
+ * non-ASCII character) becomes '_'.
+ */
+fn object_id(name: &str) -> String {
+    name.bytes()
+        .map(|b| b.to_ascii_lowercase())
+        .map(|b| if b.is_ascii_alphanumeric() || b == b'-' { b as char } else { '_' })
+        .collect()
+}
+
+fn parse_value(host: &str, sensor: &str, buf: &[u8]) -> Result<f64, Error> {
+    /* NaN is not JSON; read it as no value */
+    let text = String::from_utf8_lossy(buf).replace(":NaN", ":null").replace(": NaN", ": null");
+    let state: SensorState = serde_json::from_str(&text)?;
+    state.value.ok_or_else(|| Error::Offline { device: format!("ESPHome {} {}", host, sensor), message: "no value yet".to_string() })
+}
+
+async fn read_value(host: &str, sensor: &str) -> Result<f64, Error> {
+    let url = format!("http://{}/sensor/{}", host, object_id(sensor));
+    let (res, buf) = webapi::access(&url, webapi::HTTPMethod::GET, None, None).await?;
+    if res != 200 {
+        return Err(Error::from_status(res, format!("ESPHome returned {} for {}", res, sensor)));
+    }
+    parse_value(host, sensor, &buf)
+}
+
+/* the configured node as a TemperatureSensor */
+pub(crate) struct EsphomeSensor<'a> {
+    pub(crate) config: &'a Config,
+}
+
+#[async_trait::async_trait]
+impl TemperatureSensor for EsphomeSensor<'_> {
+    async fn read(&self) -> Result<Readings, Error> {
+        let c = self.config;
+        let (host, sensor) = match (&c.esphome_host, &c.esphome_sensor) {
+            (Some(h), Some(s)) => (h, s),
+            _ => return Err(Error::Config("esphome_host and esphome_sensor must be set".to_string())),
+        };
+        let temp = read_value(host, sensor).await?;
+        let humid = match &c.esphome_humidity_sensor {
+            Some(h) => Some(read_value(host, h).await?),
+            None => None,
+        };
+        Ok(Readings { temp, humid, co2: None, pm25: None, voc: None })
+    }
+
+    fn endpoint(&self) -> &'static str {
+        "esphome"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn esphome_sensor() {
+        assert_eq!(object_id("Bedroom Temperature"), "bedroom_temperature");
+        assert_eq!(object_id("bedroom_temperature"), "bedroom_temperature");
+        assert_eq!(object_id("Temp (°C) #2"), "temp____c___2");
+        let v = parse_value("probe.local", "t", r#"{"id": "sensor-t", "value": 21.3, "state": "21.3 °C"}"#.as_bytes());
+        assert_eq!(v.unwrap(), 21.3);
+        /* before the first reading */
+        let none = parse_value("probe.local", "t", br#"{"id": "sensor-t", "value": null, "state": "NA"}"#);
+        assert!(matches!(none, Err(Error::Offline { .. })));
+        let nan = parse_value("probe.local", "t", br#"{"id":"sensor-t","value":NaN,"state":"NA"}"#);
+        assert!(matches!(nan, Err(Error::Offline { .. })));
+        assert!(matches!(parse_value("probe.local", "t", b"<html>"), Err(Error::Parse(_))));
+    }
+}
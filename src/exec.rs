@@ -0,0 +1,228 @@
+/*
+ * External-command plugins, for devices daikawa does not support natively.
+ *
+ * Sensor (`sensor = "exec"`): `sensor_command` is run by the shell every control cycle and
+ * must print the readings as JSON on stdout, e.g. {"temp": 21.3, "humid": 45.0} (temp is
+ * required; humid, co2, pm25 and voc are optional), or just the temperature, e.g. 21.3.
+ * A failing or hanging command (over SENSOR_TIMEOUT) is reported as the sensor being offline.
+ *
+ * Thermostat (`thermostat = "exec"`): `thermostat_command` is run by the shell whenever new
+ * setpoints are to be written, e.g. to drive an IR blaster or a relay. It gets the heat
+ * setpoint, cool setpoint and duration in minutes as $1, $2 and $3, and the same as JSON on
+ * stdin: {"heat": 20.5, "cool": 24.5, "duration": 60}. Such devices report nothing back, so
+ * the room temperature is the sensor's and the setpoints are the ones last written (the
+ * targets at first); there is no outdoor temperature.
+ *
+ * Notifier (`notify = "exec"`): notify_command is run with the notification as an event
+ * JSON record on stdin, instead of the message as its argument.
+ */
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use super::*;
+
+const SENSOR_TIMEOUT: Duration = Duration::from_secs(30);
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/**
+ * parses a sensor command's output: readings as JSON, or a bare temperature
+ */
+fn parse_readings(out: &[u8]) -> Result<Readings, Error> {
+    match serde_json::from_slice(out)? {
+        serde_json::Value::Number(n) => Ok(Readings {
+            temp: n.as_f64().ok_or_else(|| Error::Invalid(format!("temperature out of range: {}", n)))?,
+            humid: None,
+            co2: None,
+            pm25: None,
+            voc: None,
+        }),
+        v => Ok(serde_json::from_value(v)?),
+    }
+}
+
+/**
+ * runs `cmd` with the shell, `args` as its positional parameters and `input` on stdin, and
+ * returns its stdout; failure is reported as `device` being offline
+ */
+async fn run(device: &str, cmd: &str, args: &[String], input: &[u8]) -> Result<Vec<u8>, Error> {
+    let offline = |message: String| Error::Offline { device: device.to_string(), message };
+    /* killed when dropped, i.e. on timeout */
+    let mut child = tokio::process::Command::new("sh").arg("-c").arg(cmd).arg("sh").args(args)
+        .stdin(Stdio::piped()).stdout(Stdio::piped()).kill_on_drop(true).spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        /* a command not reading its input is fine */
+        let _ = stdin.write_all(input).await;
+    }
+    let output = match tokio::time::timeout(SENSOR_TIMEOUT, child.wait_with_output()).await {
+        Ok(output) => output?,
+        Err(_) => return Err(offline(format!("no result in {} seconds", SENSOR_TIMEOUT.as_secs()))),
+    };
+    if !output.status.success() {
+        return Err(offline(format!("exited with {}", output.status)));
+    }
+    Ok(output.stdout)
+}
+
+/**
+ * runs `cmd` with the shell and parses its stdout as readings
+ */
+pub(crate) async fn read_sensor(cmd: &str) -> Result<Readings, Error> {
+    parse_readings(&run(&format!("sensor_command {}", cmd), cmd, &[], &[]).await?)
+}
+
+/* `sensor_command` as a TemperatureSensor */
+pub(crate) struct CommandSensor<'a> {
+    pub(crate) command: &'a str,
+}
+
+#[async_trait::async_trait]
+impl TemperatureSensor for CommandSensor<'_> {
+    async fn read(&self) -> Result<Readings, Error> {
+        read_sensor(self.command).await
+    }
+
+    fn endpoint(&self) -> &'static str {
+        "exec"
+    }
+}
+
+/* `thermostat_command` as a Thermostat */
+pub(crate) struct CommandThermostat {
+    command: String,
+    room_temp: f64,
+    heat: f64,
+    cool: f64,
+}
+
+impl CommandThermostat {
+    pub(crate) fn new(command: &str, heat: f64, cool: f64) -> CommandThermostat {
+        CommandThermostat { command: command.to_string(), room_temp: f64::NAN, heat, cool }
+    }
+}
+
+#[async_trait::async_trait]
+impl Thermostat for CommandThermostat {
+    async fn sync(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn observe_room_temp(&mut self, temp: f64) {
+        self.room_temp = temp;
+    }
+
+    fn get_temp_indoor(&self) -> f64 {
+        self.room_temp
+    }
+
+    fn get_temp_outdoor(&self) -> f64 {
+        f64::NAN
+    }
+
+    fn get_heat_setpoint(&self) -> f64 {
+        self.heat
+    }
+
+    fn get_cool_setpoint(&self) -> f64 {
+        self.cool
+    }
+
+    fn is_away(&self) -> bool {
+        false
+    }
+
+    async fn set_setpoints(&mut self, heat: f64, cool: f64, duration: u32) -> Result<(), Error> {
+        let input = serde_json::json!({ "heat": heat, "cool": cool, "duration": duration }).to_string();
+        let args = [heat.to_string(), cool.to_string(), duration.to_string()];
+        run(&format!("thermostat_command {}", self.command), &self.command, &args, input.as_bytes()).await?;
+        self.heat = heat;
+        self.cool = cool;
+        Ok(())
+    }
+
+    fn endpoint(&self) -> &'static str {
+        "thermostat"
+    }
+}
+
+/**
+ * runs `cmd` with the event as JSON on stdin
+ */
+pub(crate) fn notify(cmd: &str, event: &EventLog) {
+    let json = match serde_json::to_vec(event) {
+        Ok(j) => j,
+        Err(_) => return,
+    };
+    let child = tokio::process::Command::new(cmd).stdin(Stdio::piped()).kill_on_drop(true).spawn();
+    watch_notify(child, json);
+}
+
+/**
+ * feeds `input` to a notify_command and waits for it in the background, so that a slow
+ * command never holds up control; it is killed after NOTIFY_TIMEOUT
+ */
+pub(crate) fn watch_notify(child: std::io::Result<tokio::process::Child>, input: Vec<u8>) {
+    tokio::spawn(async move {
+        let result = match child {
+            Ok(mut child) => {
+                if let Some(mut stdin) = child.stdin.take() {
+                    let _ = stdin.write_all(&input).await;
+                }
+                tokio::time::timeout(NOTIFY_TIMEOUT, child.wait()).await
+            },
+            Err(e) => Ok(Err(e)),
+        };
+        match result {
+            Ok(Ok(st)) if !st.success() => print_event(&EventLog::warning(format!("notify_command exited with {}", st))),
+            Ok(Err(e)) => print_event(&EventLog::warning(format!("Failed to run notify_command: {}", e))),
+            Err(_) => print_event(&EventLog::warning(format!("notify_command did not finish in {} seconds, killed", NOTIFY_TIMEOUT.as_secs()))),
+            _ => (),
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn exec_sensor() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir();
+        let script = |name: &str, body: &str| {
+            let path = dir.join(format!("daikawa-exec-test-{}-{}", std::process::id(), name));
+            std::fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+            path.to_str().unwrap().to_string()
+        };
+        let ok = script("ok", r#"echo '{"temp": 21.5, "co2": 600}'"#);
+        let r = read_sensor(&ok).await.unwrap();
+        assert_eq!((r.temp, r.co2, r.humid), (21.5, Some(600.0), None));
+        let failing = script("fail", "exit 1");
+        assert!(matches!(read_sensor(&failing).await, Err(Error::Offline { .. })));
+        /* a shell command line printing just the temperature */
+        assert_eq!(read_sensor("echo 22.25 | cat").await.unwrap().temp, 22.25);
+        assert!(matches!(parse_readings(b"\"warm\""), Err(Error::Parse(_))));
+        std::fs::remove_file(ok).unwrap();
+        std::fs::remove_file(failing).unwrap();
+    }
+
+    #[tokio::test]
+    async fn exec_thermostat() {
+        let out = std::env::temp_dir().join(format!("daikawa-exec-thermostat-{}", std::process::id()));
+        let command = format!("{{ echo \"$1 $2 $3\"; cat; }} > {}", out.display());
+        let mut t = CommandThermostat::new(&command, 21.0, 26.0);
+        assert_eq!((t.get_heat_setpoint(), t.get_cool_setpoint()), (21.0, 26.0));
+        t.observe_room_temp(20.5);
+        assert_eq!(t.get_temp_indoor(), 20.5);
+        t.set_setpoints(20.5, 24.5, 60).await.unwrap();
+        let written = std::fs::read_to_string(&out).unwrap();
+        let (args, json) = written.split_once('\n').unwrap();
+        assert_eq!(args, "20.5 24.5 60");
+        assert_eq!(serde_json::from_str::<serde_json::Value>(json).unwrap(), serde_json::json!({ "heat": 20.5, "cool": 24.5, "duration": 60 }));
+        assert_eq!((t.get_heat_setpoint(), t.get_cool_setpoint()), (20.5, 24.5));
+        let mut failing = CommandThermostat::new("exit 1", 21.0, 26.0);
+        assert!(matches!(failing.set_setpoints(20.0, 25.0, 60).await, Err(Error::Offline { .. })));
+        assert_eq!(failing.get_heat_setpoint(), 21.0);
+        std::fs::remove_file(&out).unwrap();
+    }
+}
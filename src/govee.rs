@@ -0,0 +1,148 @@
+/*
+ * Govee H5075/H5074 thermometers (`sensor = "govee"`, feature "govee"), read locally from the
+ * Bluetooth LE advertisements they broadcast every few seconds; no cloud account or pairing is
+ * needed. A background task scans with the first Bluetooth adapter and keeps the latest
+ * reading per MAC address; a home reads the device at `govee_mac`, which is reported offline
+ * without a reading for MAX_AGE.
+ *
+ * Both models advertise under Govee's company ID 0xEC88:
+ *   H5075: 00, temperature and humidity packed in 3 bytes (big endian), battery %, ...
+ *   H5074: 00, temperature (i16 LE, 1/100 C), humidity (u16 LE, 1/100 %), battery %, ...
+ */
+use btleplug::api::{Central, CentralEvent, Manager as _, Peripheral as _, ScanFilter};
+use btleplug::platform::Manager;
+use futures::stream::StreamExt;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use super::*;
+
+const COMPANY_ID: u16 = 0xec88;
+const MAX_AGE: i64 = 10 * 60;
+/* wait before scanning again after the adapter fails */
+const RETRY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy)]
+struct Reading {
+    /* unix time */
+    at: i64,
+    temp: f64,
+    humid: f64,
+    battery: f64,
+}
+
+/* latest reading per MAC address (upper case) */
+static READINGS: Mutex<Option<HashMap<String, Reading>>> = Mutex::new(None);
+
+/**
+ * decodes the manufacturer data of an advertisement into (temperature, humidity, battery)
+ */
+fn decode(data: &[u8]) -> Option<(f64, f64, f64)> {
+    match data.len() {
+        /* H5075 */
+        6 => {
+            let packed = u32::from_be_bytes([0, data[1], data[2], data[3]]);
+            let (negative, value) = (packed & 0x800000 != 0, packed & 0x7fffff);
+            let temp = (value / 1000) as f64 / 10.0;
+            Some((if negative { -temp } else { temp }, (value % 1000) as f64 / 10.0, data[4] as f64))
+        },
+        /* H5074 */
+        7 => {
+            let temp = i16::from_le_bytes([data[1], data[2]]) as f64 / 100.0;
+            let humid = u16::from_le_bytes([data[3], data[4]]) as f64 / 100.0;
+            Some((temp, humid, data[5] as f64))
+        },
+        _ => None,
+    }
+}
+
+async fn scan() -> Result<(), btleplug::Error> {
+    let manager = Manager::new().await?;
+    let adapter = manager.adapters().await?.into_iter().next()
+        .ok_or_else(|| btleplug::Error::Other("no Bluetooth adapter".into()))?;
+    let mut events = adapter.events().await?;
+    adapter.start_scan(ScanFilter::default()).await?;
+    while let Some(event) = events.next().await {
+        let (id, data) = match event {
+            CentralEvent::ManufacturerDataAdvertisement { id, manufacturer_data } => (id, manufacturer_data),
+            _ => continue,
+        };
+        let (temp, humid, battery) = match data.get(&COMPANY_ID).and_then(|d| decode(d)) {
+            Some(r) => r,
+            None => continue,
+        };
+        let mac = adapter.peripheral(&id).await?.address().to_string().to_uppercase();
+        let reading = Reading { at: Utc::now().timestamp(), temp, humid, battery };
+        READINGS.lock().unwrap_or_else(|e| e.into_inner()).get_or_insert_with(HashMap::new).insert(mac, reading);
+    }
+    Ok(())
+}
+
+/**
+ * starts collecting advertisements in a background task
+ */
+pub fn listen() {
+    tokio::spawn(async {
+        loop {
+            match scan().await {
+                Ok(()) => print_event(&EventLog::warning("Bluetooth scan ended, restarting".to_string())),
+                Err(e) => print_event(&EventLog::warning(format!("Bluetooth scan failed: {}", e))),
+            }
+            tokio::time::sleep(RETRY_INTERVAL).await;
+        }
+    });
+}
+
+fn latest(mac: &str, now: i64) -> Result<Reading, Error> {
+    let offline = |message: String| Error::Offline { device: format!("Govee {}", mac), message };
+    let map = READINGS.lock().unwrap_or_else(|e| e.into_inner());
+    match map.as_ref().and_then(|m| m.get(&mac.to_uppercase())) {
+        Some(r) if now - r.at > MAX_AGE => Err(offline(format!("no advertisement for {} minutes", (now - r.at) / 60))),
+        Some(r) => Ok(*r),
+        None => Err(offline("no advertisement received yet".to_string())),
+    }
+}
+
+/* the thermometer at `mac` as a TemperatureSensor */
+pub(crate) struct GoveeSensor<'a> {
+    pub(crate) mac: &'a str,
+}
+
+#[async_trait::async_trait]
+impl TemperatureSensor for GoveeSensor<'_> {
+    async fn read(&self) -> Result<Readings, Error> {
+        let r = latest(self.mac, Utc::now().timestamp())?;
+        Ok(Readings { temp: r.temp, humid: Some(r.humid), co2: None, pm25: None, voc: None })
+    }
+
+    fn endpoint(&self) -> &'static str {
+        "govee"
+    }
+
+    /* battery-powered, identified by the MAC address */
+    async fn power_status(&self) -> Result<Vec<(String, PowerStatus)>, Error> {
+        let r = latest(self.mac, Utc::now().timestamp())?;
+        Ok(vec![(format!("Govee sensor {}", self.mac), PowerStatus { battery: r.battery, plugged: false })])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decode_advertisements() {
+        /* H5075: 0x036cc8 = 224456 -> 22.4 C, 45.6 % */
+        assert_eq!(decode(&[0x00, 0x03, 0x6c, 0xc8, 0x64, 0x00]), Some((22.4, 45.6, 100.0)));
+        /* below freezing, with the sign bit: 0x00d0d0 = 53456 -> -5.3 C, 45.6 % */
+        assert_eq!(decode(&[0x00, 0x80, 0xd0, 0xd0, 0x50, 0x00]), Some((-5.3, 45.6, 80.0)));
+        /* H5074: 0x0898 = 2200 -> 22.0 C, 0x1194 = 4500 -> 45.0 % */
+        assert_eq!(decode(&[0x00, 0x98, 0x08, 0x94, 0x11, 0x5a, 0x02]), Some((22.0, 45.0, 90.0)));
+        assert_eq!(decode(&[0x00, 0x01]), None);
+
+        READINGS.lock().unwrap().get_or_insert_with(HashMap::new)
+            .insert("A4:C1:38:00:00:01".to_string(), Reading { at: 1000, temp: 21.0, humid: 40.0, battery: 90.0 });
+        assert_eq!(latest("a4:c1:38:00:00:01", 1000 + MAX_AGE).unwrap().temp, 21.0);
+        assert!(matches!(latest("a4:c1:38:00:00:01", 1001 + MAX_AGE), Err(Error::Offline { .. })));
+        assert!(matches!(latest("a4:c1:38:00:00:02", 1000), Err(Error::Offline { .. })));
+    }
+}
@@ -0,0 +1,95 @@
+/*
+ * Home Assistant REST API (`hass_url`, `hass_token`), for occupancy (see occupancy.rs) and
+ * for `sensor = "home_assistant"`, which reads the temperature from the state of
+ * `hass_temperature_entity`: a sensor entity (e.g. "sensor.bedroom_temperature"), or a
+ * climate entity's current_temperature attribute. Fahrenheit entities are converted.
+ * Humidity is read from `hass_humidity_entity` if given. An entity that is "unavailable" or
+ * "unknown" is reported offline.
+ */
+use serde::Deserialize;
+use super::*;
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct Entity {
+    pub(crate) state: String,
+    #[serde(default)]
+    attributes: serde_json::Map<String, serde_json::Value>,
+}
+
+/**
+ * returns the state of `entity`
+ */
+pub(crate) async fn entity(url: &str, token: &String, entity: &str) -> Result<Entity, Error> {
+    let url = format!("{}/api/states/{}", url.trim_end_matches('/'), entity);
+    let (res, buf) = webapi::access(&url, webapi::HTTPMethod::GET, Some(token), None).await?;
+    if res != 200 {
+        return Err(Error::from_status(res, format!("Home Assistant returned {} for {}", res, entity)));
+    }
+    Ok(serde_json::from_slice(&buf)?)
+}
+
+/**
+ * returns the numeric value of an entity: its state, or else its current_temperature
+ * attribute, in Celsius if `temperature`
+ */
+fn value(id: &str, e: &Entity, temperature: bool) -> Result<f64, Error> {
+    if e.state == "unavailable" || e.state == "unknown" {
+        return Err(Error::Offline { device: format!("Home Assistant {}", id), message: format!("state is {}", e.state) });
+    }
+    let v = match e.state.parse::<f64>() {
+        Ok(v) => v,
+        Err(_) => e.attributes.get("current_temperature").and_then(|v| v.as_f64())
+            .ok_or_else(|| Error::Invalid(format!("{} is not a number: \"{}\"", id, e.state)))?,
+    };
+    if !v.is_finite() {
+        return Err(Error::Invalid(format!("{} is not a number: \"{}\"", id, e.state)));
+    }
+    let fahrenheit = e.attributes.get("unit_of_measurement").and_then(|u| u.as_str()) == Some("°F");
+    Ok(if temperature && fahrenheit { (v - 32.0) * 5.0 / 9.0 } else { v })
+}
+
+/* the configured entities as a TemperatureSensor */
+pub(crate) struct HassSensor<'a> {
+    pub(crate) config: &'a Config,
+}
+
+#[async_trait::async_trait]
+impl TemperatureSensor for HassSensor<'_> {
+    async fn read(&self) -> Result<Readings, Error> {
+        let c = self.config;
+        let (url, token, id) = match (&c.hass_url, &c.hass_token, &c.hass_temperature_entity) {
+            (Some(u), Some(t), Some(e)) => (u, t, e),
+            _ => return Err(Error::Config("hass_url, hass_token and hass_temperature_entity must be set".to_string())),
+        };
+        let temp = value(id, &entity(url, token, id).await?, true)?;
+        let humid = match &c.hass_humidity_entity {
+            Some(h) => Some(value(h, &entity(url, token, h).await?, false)?),
+            None => None,
+        };
+        Ok(Readings { temp, humid, co2: None, pm25: None, voc: None })
+    }
+
+    fn endpoint(&self) -> &'static str {
+        "home_assistant"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn entity_values() {
+        let e = |json: &str| serde_json::from_str::<Entity>(json).unwrap();
+        let sensor = e(r#"{"entity_id": "sensor.t", "state": "21.5", "attributes": {"unit_of_measurement": "°C"}}"#);
+        assert_eq!(value("sensor.t", &sensor, true).unwrap(), 21.5);
+        let fahrenheit = e(r#"{"state": "77", "attributes": {"unit_of_measurement": "°F"}}"#);
+        assert_eq!(value("sensor.t", &fahrenheit, true).unwrap(), 25.0);
+        let climate = e(r#"{"state": "heat", "attributes": {"current_temperature": 20.5}}"#);
+        assert_eq!(value("climate.t", &climate, true).unwrap(), 20.5);
+        assert!(matches!(value("sensor.t", &e(r#"{"state": "unavailable"}"#), true), Err(Error::Offline { .. })));
+        assert!(matches!(value("sensor.t", &e(r#"{"state": "on"}"#), true), Err(Error::Invalid(_))));
+        assert!(matches!(value("sensor.t", &e(r#"{"state": "nan"}"#), true), Err(Error::Invalid(_))));
+        assert!(matches!(value("sensor.t", &e(r#"{"state": "inf"}"#), true), Err(Error::Invalid(_))));
+    }
+}
@@ -0,0 +1,87 @@
+/*
+ * Humidity-derived temperatures.
+ *
+ * "Feels like" values combine dry-bulb temperature (C) and relative humidity (%):
+ * - humidex: Environment Canada's humidex, T + 0.5555 * (e - 10) with e the vapor pressure in hPa
+ * - heat_index: NOAA's heat index (Rothfusz regression, simple formula below ~27 C)
+ *
+ * The dew point (Magnus formula) tells when surfaces get cold enough for condensation.
+ */
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum FeelsLike {
+    Humidex,
+    HeatIndex,
+}
+
+pub(crate) fn parse_feels_like(s: &str) -> Option<FeelsLike> {
+    match s {
+        "humidex" => Some(FeelsLike::Humidex),
+        "heat_index" => Some(FeelsLike::HeatIndex),
+        _ => None,
+    }
+}
+
+/**
+ * returns water vapor pressure (hPa) at temperature `t` (C) and relative humidity `rh` (%)
+ */
+fn vapor_pressure(t: f64, rh: f64) -> f64 {
+    6.112 * (17.67 * t / (t + 243.5)).exp() * rh / 100.0
+}
+
+fn humidex(t: f64, rh: f64) -> f64 {
+    t + 0.5555 * (vapor_pressure(t, rh) - 10.0)
+}
+
+fn heat_index(t: f64, rh: f64) -> f64 {
+    let f = t * 9.0 / 5.0 + 32.0;
+    let simple = 0.5 * (f + 61.0 + (f - 68.0) * 1.2 + rh * 0.094);
+    let hi = if (simple + f) / 2.0 < 80.0 {
+        simple
+    } else {
+        -42.379 + 2.04901523 * f + 10.14333127 * rh - 0.22475541 * f * rh
+            - 0.00683783 * f * f - 0.05481717 * rh * rh + 0.00122874 * f * f * rh
+            + 0.00085282 * f * rh * rh - 0.00000199 * f * f * rh * rh
+    };
+    (hi - 32.0) * 5.0 / 9.0
+}
+
+/**
+ * returns the dew point (C) at temperature `t` (C) and relative humidity `rh` (%)
+ */
+pub(crate) fn dew_point(t: f64, rh: f64) -> f64 {
+    let gamma = (rh.max(1.0) / 100.0).ln() + 17.67 * t / (t + 243.5);
+    243.5 * gamma / (17.67 - gamma)
+}
+
+/**
+ * returns the "feels like" temperature (C) by the given formula
+ */
+pub(crate) fn feels_like(formula: FeelsLike, t: f64, rh: f64) -> f64 {
+    match formula {
+        FeelsLike::Humidex => humidex(t, rh),
+        FeelsLike::HeatIndex => heat_index(t, rh),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn feels_like_temp() {
+        /* humidex 30 C / 70% is about 41 */
+        assert!((feels_like(FeelsLike::Humidex, 30.0, 70.0) - 40.9).abs() < 0.5);
+        /* heat index 32 C (90 F) / 70% is about 106 F (41 C) */
+        assert!((feels_like(FeelsLike::HeatIndex, 32.2, 70.0) - 41.1).abs() < 0.5);
+        /* mild conditions stay close to dry-bulb */
+        assert!((feels_like(FeelsLike::HeatIndex, 21.0, 50.0) - 21.0).abs() < 1.0);
+        assert!(parse_feels_like("wind_chill").is_none());
+    }
+
+    #[test]
+    fn dew_point_temp() {
+        assert!((dew_point(25.0, 60.0) - 16.7).abs() < 0.2);
+        assert!((dew_point(20.0, 100.0) - 20.0).abs() < 0.01);
+    }
+}
@@ -0,0 +1,212 @@
+/*
+ * MQTT command channel and MQTT sensors (`mqtt_host`, needs the "mqtt" feature).
+ *
+ * Subscribes to `mqtt_command_topic` and forwards commands (see command.rs) to the
+ * controllers of the homes they are addressed to. Malformed messages are logged and ignored.
+ *
+ * Homes with `sensor = "zigbee2mqtt"` read the device Zigbee2MQTT publishes on
+ * `zigbee2mqtt_topic` (e.g. "zigbee2mqtt/bedroom_sensor"), whose state carries `temperature`
+ * and optionally `humidity`, `co2`, `pm25`, `voc` and `battery`. A device without a message
+ * for `zigbee2mqtt_max_age` minutes is reported offline.
+ *
+ * Homes with `sensor = "mqtt"` read the temperature any publisher sends on `mqtt_sensor_topic`:
+ * a bare number (e.g. "21.3"), or JSON with the number at `mqtt_sensor_key`, a dot-separated
+ * path such as "sensors.temp". A topic without a message for `mqtt_sensor_max_age` minutes is
+ * reported offline.
+ */
+use rumqttc::{AsyncClient, MqttOptions, Packet, QoS};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedSender;
+use super::*;
+
+/* wait before reconnecting after a connection error */
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/* state published by Zigbee2MQTT; other fields (linkquality, voltage, ...) are ignored */
+#[derive(Debug, Clone, Copy, Deserialize)]
+struct DeviceState {
+    temperature: f64,
+    humidity: Option<f64>,
+    co2: Option<f64>,
+    pm25: Option<f64>,
+    voc: Option<f64>,
+    battery: Option<f64>,
+}
+
+/* (unix time, payload) of a message */
+type Message = (i64, Vec<u8>);
+
+/* latest message per sensor topic */
+static MESSAGES: Mutex<Option<HashMap<String, Message>>> = Mutex::new(None);
+
+/**
+ * starts receiving commands, and the states of `sensor_topics`, in a background task if
+ * `mqtt_host` is configured
+ */
+pub fn spawn(config: &Config, sensor_topics: Vec<String>, homes: Vec<(Option<String>, UnboundedSender<controller::Event>)>) {
+    let host = match &config.mqtt_host {
+        Some(h) => h.clone(),
+        None => return,
+    };
+    let topic = config.mqtt_command_topic.clone();
+    let mut opts = MqttOptions::new(format!("daikawa-{}", std::process::id()), host.clone(), config.mqtt_port);
+    opts.set_keep_alive(Duration::from_secs(30));
+    if let (Some(user), Some(password)) = (&config.mqtt_username, &config.mqtt_password) {
+        opts.set_credentials(user, password);
+    }
+    tokio::spawn(async move { run(opts, &host, &topic, &sensor_topics, homes).await });
+}
+
+async fn run(opts: MqttOptions, host: &str, topic: &str, sensor_topics: &[String], homes: Vec<(Option<String>, UnboundedSender<controller::Event>)>) {
+    let (client, mut eventloop) = AsyncClient::new(opts, 10);
+    loop {
+        match eventloop.poll().await {
+            /* (re)subscribe on every connection, as the broker may not keep the session */
+            Ok(rumqttc::Event::Incoming(Packet::ConnAck(_))) => {
+                for t in std::iter::once(topic).chain(sensor_topics.iter().map(String::as_str)) {
+                    /* queued for the event loop, which this task polls */
+                    if let Err(e) = client.try_subscribe(t, QoS::AtLeastOnce) {
+                        print_event(&EventLog::warning(format!("Failed to subscribe to {}: {}", t, e)));
+                    }
+                }
+            },
+            Ok(rumqttc::Event::Incoming(Packet::Publish(p))) if sensor_topics.contains(&p.topic) => {
+                record(&p.topic, &p.payload, Utc::now().timestamp());
+            },
+            Ok(rumqttc::Event::Incoming(Packet::Publish(p))) => {
+                if let Err(e) = command::dispatch(&homes, &p.payload) {
+                    print_event(&EventLog::warning(format!("Ignoring MQTT command: {}", e)));
+                }
+            },
+            Ok(_) => (),
+            Err(e) => {
+                print_event(&EventLog::warning(format!("MQTT connection to {} failed: {}", host, e)));
+                tokio::time::sleep(RECONNECT_DELAY).await;
+            },
+        }
+    }
+}
+
+fn record(topic: &str, payload: &[u8], now: i64) {
+    MESSAGES.lock().unwrap_or_else(|e| e.into_inner()).get_or_insert_with(HashMap::new).insert(topic.to_string(), (now, payload.to_vec()));
+}
+
+/**
+ * returns the latest payload on `topic`, if not older than `max_age` minutes
+ */
+fn latest(topic: &str, max_age: u32, now: i64) -> Result<Vec<u8>, Error> {
+    let offline = |message: String| Error::Offline { device: format!("MQTT topic {}", topic), message };
+    let map = MESSAGES.lock().unwrap_or_else(|e| e.into_inner());
+    match map.as_ref().and_then(|m| m.get(topic)) {
+        Some((at, _)) if now - at > max_age as i64 * 60 => Err(offline(format!("no message for {} minutes", (now - at) / 60))),
+        Some((_, payload)) => Ok(payload.clone()),
+        None => Err(offline("no message received yet".to_string())),
+    }
+}
+
+/**
+ * parses a payload as a bare number, or as JSON with the number (or a numeric string) at the
+ * dot-separated `key` path. NaN and infinities are rejected.
+ */
+fn parse_value(payload: &[u8], key: Option<&str>) -> Result<f64, Error> {
+    let key = match key {
+        Some(k) => k,
+        None => {
+            let text = String::from_utf8_lossy(payload);
+            return text.trim().parse().ok().filter(|v: &f64| v.is_finite())
+                .ok_or_else(|| Error::Invalid(format!("not a number: \"{}\"", text.trim())));
+        },
+    };
+    let json: serde_json::Value = serde_json::from_slice(payload)?;
+    let value = key.split('.').try_fold(&json, |v, k| v.get(k))
+        .ok_or_else(|| Error::Invalid(format!("no \"{}\" in the message", key)))?;
+    let number = match value {
+        serde_json::Value::Number(n) => n.as_f64(),
+        serde_json::Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }.filter(|v: &f64| v.is_finite());
+    number.ok_or_else(|| Error::Invalid(format!("\"{}\" is not a number: {}", key, value)))
+}
+
+/* a topic of any publisher as a TemperatureSensor */
+pub(crate) struct MqttSensor<'a> {
+    pub(crate) topic: &'a str,
+    pub(crate) key: Option<&'a str>,
+    pub(crate) max_age: u32,
+}
+
+#[async_trait::async_trait]
+impl TemperatureSensor for MqttSensor<'_> {
+    async fn read(&self) -> Result<Readings, Error> {
+        let temp = parse_value(&latest(self.topic, self.max_age, Utc::now().timestamp())?, self.key)?;
+        Ok(Readings { temp, humid: None, co2: None, pm25: None, voc: None })
+    }
+
+    fn endpoint(&self) -> &'static str {
+        "mqtt"
+    }
+}
+
+fn zigbee2mqtt_state(topic: &str, max_age: u32, now: i64) -> Result<DeviceState, Error> {
+    Ok(serde_json::from_slice(&latest(topic, max_age, now)?)?)
+}
+
+/* a Zigbee2MQTT device as a TemperatureSensor */
+pub(crate) struct Zigbee2MqttSensor<'a> {
+    pub(crate) topic: &'a str,
+    pub(crate) max_age: u32,
+}
+
+#[async_trait::async_trait]
+impl TemperatureSensor for Zigbee2MqttSensor<'_> {
+    async fn read(&self) -> Result<Readings, Error> {
+        let s = zigbee2mqtt_state(self.topic, self.max_age, Utc::now().timestamp())?;
+        Ok(Readings { temp: s.temperature, humid: s.humidity, co2: s.co2, pm25: s.pm25, voc: s.voc })
+    }
+
+    fn endpoint(&self) -> &'static str {
+        "zigbee2mqtt"
+    }
+
+    /* battery-powered devices report their level; the device is named by its topic */
+    async fn power_status(&self) -> Result<Vec<(String, PowerStatus)>, Error> {
+        let s = zigbee2mqtt_state(self.topic, self.max_age, Utc::now().timestamp())?;
+        let device = format!("Zigbee2MQTT device {}", self.topic);
+        Ok(s.battery.map(|battery| (device, PowerStatus { battery, plugged: false })).into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn zigbee2mqtt() {
+        let topic = "zigbee2mqtt/test_sensor";
+        assert!(matches!(zigbee2mqtt_state(topic, 15, 1000), Err(Error::Offline { .. })));
+        record(topic, br#"{"battery": 87, "humidity": 45.5, "linkquality": 120, "temperature": 21.3, "voltage": 2975}"#, 1000);
+        let s = zigbee2mqtt_state(topic, 15, 1000 + 15 * 60).unwrap();
+        assert_eq!((s.temperature, s.humidity, s.co2, s.battery), (21.3, Some(45.5), None, Some(87.0)));
+        assert!(matches!(zigbee2mqtt_state(topic, 15, 1001 + 15 * 60), Err(Error::Offline { .. })));
+        record(topic, br#"{"humidity": 45.5}"#, 2000);
+        assert!(matches!(zigbee2mqtt_state(topic, 15, 2000), Err(Error::Parse(_))));
+    }
+
+    #[test]
+    fn mqtt_values() {
+        assert_eq!(parse_value(b" 21.3\n", None).unwrap(), 21.3);
+        assert!(matches!(parse_value(b"warm", None), Err(Error::Invalid(_))));
+        assert!(matches!(parse_value(b"nan", None), Err(Error::Invalid(_))));
+        assert!(matches!(parse_value(b"inf", None), Err(Error::Invalid(_))));
+        assert!(matches!(parse_value(br#"{"temp": "-inf"}"#, Some("temp")), Err(Error::Invalid(_))));
+        let json = br#"{"sensors": {"temp": 22.5, "text": "23.0", "on": true}}"#;
+        assert_eq!(parse_value(json, Some("sensors.temp")).unwrap(), 22.5);
+        assert_eq!(parse_value(json, Some("sensors.text")).unwrap(), 23.0);
+        assert!(matches!(parse_value(json, Some("sensors.on")), Err(Error::Invalid(_))));
+        assert!(matches!(parse_value(json, Some("sensors.humid")), Err(Error::Invalid(_))));
+        assert!(matches!(parse_value(b"21.3", Some("temp")), Err(Error::Invalid(_))));
+    }
+}
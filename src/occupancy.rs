@@ -0,0 +1,62 @@
+/*
+ * Whether anyone is home (`occupancy`), which pauses control while away.
+ *
+ * daikin:         Daikin One+'s geofencing (default)
+ * home_assistant: Home Assistant's `person.*` / `device_tracker.*` entities listed in
+ *                 `occupancy_entities`, read from `hass_url` with the long-lived access token
+ *                 `hass_token` (see hass.rs). The home is occupied while any of them is "home". If Home
+ *                 Assistant cannot be reached, Daikin's geofencing is used for that cycle.
+ */
+use super::*;
+
+/**
+ * returns whether any of the entity states means someone is home
+ */
+fn occupied(states: &[String]) -> bool {
+    states.iter().any(|s| s == "home")
+}
+
+async fn hass_away(config: &Config) -> Result<bool, Error> {
+    let (url, token) = match (&config.hass_url, &config.hass_token) {
+        (Some(u), Some(t)) => (u, t),
+        _ => return Err(Error::Config("hass_url and hass_token must be set".to_string())),
+    };
+    let mut states = Vec::new();
+    for e in config.occupancy_entities.iter() {
+        states.push(hass::entity(url, token, e).await?.state);
+    }
+    Ok(!occupied(&states))
+}
+
+/**
+ * returns whether the home is unoccupied
+ */
+pub(crate) async fn is_away(config: &Config, thermostat: &dyn Thermostat) -> bool {
+    if config.occupancy != "home_assistant" {
+        return thermostat.is_away();
+    }
+    match hass_away(config).await {
+        Ok(away) => away,
+        Err(e) => {
+            print_event(&EventLog {
+                endpoint: Some("home_assistant"),
+                http_status: e.http_status(),
+                ..EventLog::warning(format!("Failed to read occupancy from Home Assistant, using Daikin geofencing: {}", e))
+            });
+            thermostat.is_away()
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn occupancy() {
+        let states = |s: &[&str]| s.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert!(occupied(&states(&["not_home", "home"])));
+        assert!(!occupied(&states(&["not_home", "work"])));
+        assert!(!occupied(&[]));
+    }
+}
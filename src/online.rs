@@ -0,0 +1,143 @@
+/*
+ * Per-device online state.
+ *
+ * A device is marked offline when its API reports it as such (Error::Offline), or when an
+ * Awair sensor has stopped uploading readings (Error::StaleData). A notification is sent once
+ * the device has been offline for `offline_alert` minutes, and another one when it comes back.
+ *
+ * Battery-powered Awair Omni sensors are checked every BATTERY_CHECK_INTERVAL, and a
+ * notification is sent once when one running on battery falls to `battery_alert` percent.
+ */
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use super::*;
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct DeviceStatus {
+    /* unix time the device was first seen offline */
+    offline_since: Option<i64>,
+    alerted: bool,
+}
+
+impl DeviceStatus {
+    /**
+     * records whether the device is online; returns true if it has just been offline
+     * for `limit` minutes
+     */
+    fn record(&mut self, online: bool, now: i64, limit: u32) -> bool {
+        if online {
+            self.offline_since = None;
+            self.alerted = false;
+            return false;
+        }
+        let since = *self.offline_since.get_or_insert(now);
+        if !self.alerted && now - since >= limit as i64 * 60 {
+            self.alerted = true;
+            return true;
+        }
+        false
+    }
+}
+
+/* seconds between battery checks; the level changes slowly */
+const BATTERY_CHECK_INTERVAL: i64 = 60 * 60;
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct BatteryState {
+    /* lowest battery level seen at the last check */
+    level: Option<f64>,
+    checked_at: Option<i64>,
+    alerted: bool,
+}
+
+/**
+ * returns the lowest battery level (%) of battery-powered sensors, checking it at most
+ * every BATTERY_CHECK_INTERVAL and alerting once when a sensor on battery runs low
+ */
+pub(crate) async fn check_battery(config: &Config, state: &mut BatteryState, sensor: &dyn TemperatureSensor, now: i64) -> Option<f64> {
+    if state.checked_at.is_some_and(|at| now - at < BATTERY_CHECK_INTERVAL) {
+        return state.level;
+    }
+    state.checked_at = Some(now);
+    let statuses = match sensor.power_status().await {
+        Ok(s) => s,
+        Err(e) => {
+            print_event(&EventLog {
+                endpoint: Some(sensor.endpoint()),
+                http_status: e.http_status(),
+                ..EventLog::warning(format!("Failed to get sensor power status: {}", e))
+            });
+            return state.level;
+        },
+    };
+    state.level = statuses.iter().map(|(_, p)| p.battery).reduce(f64::min);
+    let low: Vec<String> = statuses.iter()
+        .filter(|(_, p)| !p.plugged && battery_low(p.battery, config.battery_alert))
+        .map(|(device, p)| format!("{} ({:.0}%)", device, p.battery))
+        .collect();
+    if !low.is_empty() && !state.alerted {
+        alert(config, &format!("battery low, charge the sensor: {}", low.join(", ")));
+    }
+    state.alerted = !low.is_empty();
+    state.level
+}
+
+fn battery_low(level: f64, threshold: f64) -> bool {
+    threshold > 0.0 && level <= threshold
+}
+
+/* devices by their name in Error::Offline/StaleData */
+pub type Devices = BTreeMap<String, DeviceStatus>;
+
+/**
+ * updates online state from the result of a control cycle: every device is online after
+ * a successful cycle, and the device named in an Error::Offline or StaleData is offline
+ */
+pub(crate) fn update(config: &Config, devices: &mut Devices, error: Option<&Error>, now: i64) {
+    let offline = match error {
+        Some(Error::Offline { device, .. } | Error::StaleData { device, .. }) => Some(device.as_str()),
+        /* other failures tell nothing about the devices */
+        Some(_) => return,
+        None => None,
+    };
+    if let Some(device) = offline {
+        devices.entry(device.to_string()).or_default();
+    }
+    for (name, status) in devices.iter_mut() {
+        let online = offline != Some(name.as_str());
+        let was_alerted = status.alerted;
+        let since = status.offline_since;
+        if status.record(online, now, config.offline_alert.unwrap_or(u32::MAX)) {
+            alert(config, &format!("{} has been offline for {} minutes", name, (now - since.unwrap_or(now)) / 60));
+        } else if online && was_alerted {
+            let minutes = since.map_or(0, |s| (now - s) / 60);
+            print_event(&EventLog::info(format!("{} is back online after {} minutes", name, minutes)));
+            if config.notify_command.is_some() {
+                notify(config, &format!("{} is back online", name));
+            }
+        }
+    }
+    devices.retain(|_, s| s.offline_since.is_some());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn offline_alert() {
+        let mut status = DeviceStatus::default();
+        assert!(!status.record(false, 0, 30));
+        assert!(!status.record(false, 20 * 60, 30));
+        assert!(status.record(false, 30 * 60, 30));
+        /* notified once per outage */
+        assert!(!status.record(false, 60 * 60, 30));
+        assert!(!status.record(true, 70 * 60, 30));
+        assert_eq!(status.offline_since, None);
+        assert!(!status.record(false, 80 * 60, 30));
+
+        assert!(battery_low(20.0, 20.0));
+        assert!(!battery_low(21.0, 20.0));
+        assert!(!battery_low(0.0, 0.0));
+    }
+}
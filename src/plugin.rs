@@ -0,0 +1,211 @@
+/*
+ * External plugins (`plugin_command`) for sensors (sensor = "plugin") and thermostats
+ * (thermostat = "plugin") daikawa does not support natively. The program is started once and
+ * kept running; daikawa writes one JSON request per line to its stdin and reads one JSON reply
+ * per line from its stdout:
+ *
+ *   -> {"method": "read_sensor"}
+ *   <- {"temp": 21.3, "humid": 45.0}
+ *   -> {"method": "get_state"}
+ *   <- {"indoor_temp": 20.5, "outdoor_temp": 3.0, "heat_setpoint": 20.0, "cool_setpoint": 25.0, "away": false}
+ *   -> {"method": "set_setpoints", "heat": 20.5, "cool": 24.5, "duration": 60}
+ *   <- {}
+ *
+ * read_sensor replies with readings as sensor = "exec" prints them. "away" is optional. Any
+ * request may be answered with {"error": "<message>"}. A program that exits, or does not
+ * reply within PLUGIN_TIMEOUT, is reported offline and started again on the next request.
+ * Its stderr is passed through to daikawa's.
+ */
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use super::*;
+
+const PLUGIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+struct Process {
+    /* killed when dropped */
+    _child: Child,
+    stdin: ChildStdin,
+    replies: Lines<BufReader<ChildStdout>>,
+}
+
+/* running plugins by command, shared by the sensor and thermostat of a home */
+static PLUGINS: Mutex<Option<HashMap<String, Arc<tokio::sync::Mutex<Process>>>>> = Mutex::new(None);
+
+fn start(command: &str) -> Result<Process, Error> {
+    let mut child = Command::new(command).stdin(Stdio::piped()).stdout(Stdio::piped()).kill_on_drop(true).spawn()?;
+    let (stdin, stdout) = match (child.stdin.take(), child.stdout.take()) {
+        (Some(i), Some(o)) => (i, o),
+        _ => return Err(Error::Internal(format!("plugin {}: no stdin/stdout", command))),
+    };
+    Ok(Process { _child: child, stdin, replies: BufReader::new(stdout).lines() })
+}
+
+fn process(command: &str) -> Result<Arc<tokio::sync::Mutex<Process>>, Error> {
+    let mut plugins = PLUGINS.lock().unwrap_or_else(|e| e.into_inner());
+    let plugins = plugins.get_or_insert_with(HashMap::new);
+    if let Some(p) = plugins.get(command) {
+        return Ok(p.clone());
+    }
+    let p = Arc::new(tokio::sync::Mutex::new(start(command)?));
+    plugins.insert(command.to_string(), p.clone());
+    Ok(p)
+}
+
+async fn exchange(p: &mut Process, request: &serde_json::Value) -> Result<String, String> {
+    let line = format!("{}\n", request);
+    p.stdin.write_all(line.as_bytes()).await.map_err(|e| format!("failed to write request: {}", e))?;
+    p.stdin.flush().await.map_err(|e| format!("failed to write request: {}", e))?;
+    match tokio::time::timeout(PLUGIN_TIMEOUT, p.replies.next_line()).await {
+        Ok(Ok(Some(line))) => Ok(line),
+        Err(_) => Err(format!("no reply in {} seconds", PLUGIN_TIMEOUT.as_secs())),
+        Ok(_) => Err("exited".to_string()),
+    }
+}
+
+/**
+ * returns an {"error": ...} reply's message
+ */
+fn reply_error(reply: &serde_json::Value) -> Option<String> {
+    reply.get("error").map(|e| e.as_str().map_or_else(|| e.to_string(), |s| s.to_string()))
+}
+
+/**
+ * sends `request` to the plugin `command` and returns its reply
+ */
+pub(crate) async fn request(command: &str, request: serde_json::Value) -> Result<serde_json::Value, Error> {
+    let offline = |message: String| Error::Offline { device: format!("plugin {}", command), message };
+    let plugin = process(command)?;
+    let line = exchange(&mut *plugin.lock().await, &request).await;
+    let line = match line {
+        Ok(l) => l,
+        Err(message) => {
+            /* out of step with its replies, or gone: start afresh next time */
+            if let Some(plugins) = PLUGINS.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+                plugins.remove(command);
+            }
+            return Err(offline(message));
+        },
+    };
+    let reply: serde_json::Value = serde_json::from_str(&line)?;
+    match reply_error(&reply) {
+        Some(message) => Err(offline(message)),
+        None => Ok(reply),
+    }
+}
+
+/* the plugin as a TemperatureSensor */
+pub(crate) struct PluginSensor<'a> {
+    pub(crate) command: &'a str,
+}
+
+#[async_trait::async_trait]
+impl TemperatureSensor for PluginSensor<'_> {
+    async fn read(&self) -> Result<Readings, Error> {
+        let reply = request(self.command, serde_json::json!({ "method": "read_sensor" })).await?;
+        Ok(serde_json::from_value(reply)?)
+    }
+
+    fn endpoint(&self) -> &'static str {
+        "plugin"
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct State {
+    indoor_temp: f64,
+    outdoor_temp: f64,
+    heat_setpoint: f64,
+    cool_setpoint: f64,
+    #[serde(default)]
+    away: bool,
+}
+
+/* the plugin as a Thermostat */
+pub(crate) struct PluginThermostat {
+    command: String,
+    state: State,
+}
+
+impl PluginThermostat {
+    pub(crate) fn new(command: &str) -> PluginThermostat {
+        PluginThermostat { command: command.to_string(), state: State::default() }
+    }
+}
+
+#[async_trait::async_trait]
+impl Thermostat for PluginThermostat {
+    async fn sync(&mut self) -> Result<(), Error> {
+        let reply = request(&self.command, serde_json::json!({ "method": "get_state" })).await?;
+        self.state = serde_json::from_value(reply)?;
+        Ok(())
+    }
+
+    fn get_temp_indoor(&self) -> f64 {
+        self.state.indoor_temp
+    }
+
+    fn get_temp_outdoor(&self) -> f64 {
+        self.state.outdoor_temp
+    }
+
+    fn get_heat_setpoint(&self) -> f64 {
+        self.state.heat_setpoint
+    }
+
+    fn get_cool_setpoint(&self) -> f64 {
+        self.state.cool_setpoint
+    }
+
+    fn is_away(&self) -> bool {
+        self.state.away
+    }
+
+    async fn set_setpoints(&mut self, heat: f64, cool: f64, duration: u32) -> Result<(), Error> {
+        request(&self.command, serde_json::json!({ "method": "set_setpoints", "heat": heat, "cool": cool, "duration": duration })).await?;
+        self.state.heat_setpoint = heat;
+        self.state.cool_setpoint = cool;
+        Ok(())
+    }
+
+    fn endpoint(&self) -> &'static str {
+        "thermostat"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn plugin_protocol() {
+        use std::os::unix::fs::PermissionsExt;
+        let path = std::env::temp_dir().join(format!("daikawa-plugin-test-{}", std::process::id()));
+        std::fs::write(&path, r#"#!/bin/sh
+while read line; do
+  case "$line" in
+    *read_sensor*) echo '{"temp": 21.5, "humid": 40.0}' ;;
+    *get_state*) echo '{"indoor_temp": 20.0, "outdoor_temp": 5.0, "heat_setpoint": 19.0, "cool_setpoint": 26.0}' ;;
+    *) echo '{"error": "unsupported"}' ;;
+  esac
+done
+"#).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let command = path.to_str().unwrap();
+
+        let r = PluginSensor { command }.read().await.unwrap();
+        assert_eq!(r.temp, 21.5);
+        assert_eq!(r.humid, Some(40.0));
+        let mut t = PluginThermostat::new(command);
+        t.sync().await.unwrap();
+        assert_eq!((t.get_temp_indoor(), t.get_heat_setpoint(), t.is_away()), (20.0, 19.0, false));
+        assert!(matches!(t.set_setpoints(20.0, 25.0, 60).await, Err(Error::Offline { .. })));
+        assert_eq!(t.get_heat_setpoint(), 19.0);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
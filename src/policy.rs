@@ -0,0 +1,424 @@
+/*
+ * Control policies: how setpoints are computed from readings and targets (`policy`).
+ *
+ * offset:   shift the targets by the difference between the sensor and Daikin (default)
+ * deadband: like offset, but keep the current setpoints unless they are off by more than
+ *           `policy_deadband` degrees, to save thermostat writes
+ * pid:      offset plus a PID correction on the comfort error (how far the sensor is outside
+ *           the targets), with gains `pid_kp`, `pid_ki` (per minute) and `pid_kd` (minutes)
+ * bangbang: run the equipment outright while the sensor is outside the targets, by setting
+ *           setpoints `bangbang_swing` degrees past Daikin's own temperature, and keep both
+ *           setpoints that far away from it (neutral) while inside; clamped to the sane range
+ * script:   a Rhai script (see script.rs)
+ * autotune: a relay test estimating PID gains, meant to run overnight. The equipment is
+ *           switched like bangbang around the heating target (cooling target if it is warmer
+ *           outside) with AUTOTUNE_HYSTERESIS, and after AUTOTUNE_CYCLES oscillations the
+ *           gains are computed from their period and amplitude, logged and saved in the state
+ *           file. The test is abandoned if the sensor strays AUTOTUNE_ABORT degrees from the
+ *           target or it does not finish in AUTOTUNE_MAX_HOURS. Once done (or abandoned), it
+ *           behaves like offset.
+ *
+ * A [shadow] table may set a second policy with its own settings (SETTINGS), which is computed
+ * every cycle and logged next to the live one but never applied, to compare candidates on real data.
+ *
+ * A new policy implements ControlPolicy and is added to `select`.
+ */
+use serde::{Deserialize, Serialize};
+use super::*;
+
+const AUTOTUNE_HYSTERESIS: f64 = 0.2;
+const AUTOTUNE_CYCLES: u32 = 3;
+const AUTOTUNE_ABORT: f64 = 2.0;
+const AUTOTUNE_MAX_HOURS: i64 = 10;
+/* setpoints are kept within this many degrees of the targets during autotune */
+const AUTOTUNE_CLAMP: f64 = 4.0;
+
+/* bound on the integral term (degrees), so it does not wind up while the equipment cannot keep up */
+const PID_INTEGRAL_LIMIT: f64 = 3.0;
+
+pub(crate) struct Inputs {
+    /* temperature controlled on */
+    pub(crate) sensor_temp: f64,
+    pub(crate) daikin_temp: f64,
+    pub(crate) outdoor_temp: f64,
+    pub(crate) targets: (f64, f64),
+    /* Daikin's current setpoints */
+    pub(crate) current: (f64, f64),
+    /* passed on to scripts only */
+    #[cfg(feature = "script")]
+    pub(crate) readings: Readings,
+    pub(crate) now: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Action {
+    /* apply (heat, cool) setpoints */
+    Set((f64, f64)),
+    /* leave the current setpoints */
+    Keep,
+}
+
+/* state carried across cycles by policies that need it */
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct PolicyState {
+    #[serde(default)]
+    integral: f64,
+    /* (unix time, error) of the previous cycle */
+    #[serde(default)]
+    last_error: Option<(i64, f64)>,
+    /* progress of the autotune relay test, and the gains it suggested */
+    #[serde(default)]
+    autotune: Option<Autotune>,
+    #[serde(default)]
+    tuned: Option<Gains>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct Gains {
+    pub kp: f64,
+    pub ki: f64,
+    pub kd: f64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Autotune {
+    started: i64,
+    heating: bool,
+    relay_on: bool,
+    /* unix time the relay was first and last switched on; a period runs from one switch-on to the next */
+    first_on: Option<i64>,
+    last_on: Option<i64>,
+    cycles: u32,
+    /* extremes of the current period, and the sum of the amplitudes of completed ones */
+    high: f64,
+    low: f64,
+    amplitude_sum: f64,
+    done: bool,
+}
+
+pub(crate) trait ControlPolicy {
+    fn decide(&self, inputs: &Inputs, state: &mut PolicyState) -> Result<Action, Error>;
+}
+
+struct Offset;
+
+impl ControlPolicy for Offset {
+    fn decide(&self, i: &Inputs, _: &mut PolicyState) -> Result<Action, Error> {
+        Ok(Action::Set(calc_new_setpoints(i.sensor_temp, i.daikin_temp, i.targets.0, i.targets.1)))
+    }
+}
+
+struct Deadband {
+    width: f64,
+}
+
+impl ControlPolicy for Deadband {
+    fn decide(&self, i: &Inputs, state: &mut PolicyState) -> Result<Action, Error> {
+        let sp = match Offset.decide(i, state)? {
+            Action::Set(sp) => sp,
+            Action::Keep => return Ok(Action::Keep),
+        };
+        if (sp.0 - i.current.0).abs() <= self.width && (sp.1 - i.current.1).abs() <= self.width {
+            return Ok(Action::Keep);
+        }
+        Ok(Action::Set(sp))
+    }
+}
+
+struct Pid {
+    kp: f64,
+    ki: f64,
+    kd: f64,
+}
+
+/**
+ * returns how far the temperature is below the heating target (positive) or above the
+ * cooling target (negative); 0 in between
+ */
+pub(crate) fn comfort_error(temp: f64, (heat, cool): (f64, f64)) -> f64 {
+    if temp < heat {
+        heat - temp
+    } else if temp > cool {
+        cool - temp
+    } else {
+        0.0
+    }
+}
+
+impl ControlPolicy for Pid {
+    fn decide(&self, i: &Inputs, state: &mut PolicyState) -> Result<Action, Error> {
+        let (heat, cool) = calc_new_setpoints(i.sensor_temp, i.daikin_temp, i.targets.0, i.targets.1);
+        let error = comfort_error(i.sensor_temp, i.targets);
+        let (minutes, derivative) = match state.last_error {
+            Some((at, prev)) if i.now > at => {
+                let minutes = (i.now - at) as f64 / 60.0;
+                (minutes, (error - prev) / minutes)
+            },
+            _ => (0.0, 0.0),
+        };
+        if self.ki > 0.0 {
+            let limit = PID_INTEGRAL_LIMIT / self.ki;
+            state.integral = (state.integral + error * minutes).clamp(-limit, limit);
+        }
+        state.last_error = Some((i.now, error));
+        let correction = self.kp * error + self.ki * state.integral + self.kd * derivative;
+        Ok(Action::Set((heat + correction, cool + correction)))
+    }
+}
+
+struct BangBang {
+    swing: f64,
+}
+
+impl ControlPolicy for BangBang {
+    fn decide(&self, i: &Inputs, _: &mut PolicyState) -> Result<Action, Error> {
+        let (d, swing) = (i.daikin_temp, self.swing);
+        let sp = if i.sensor_temp < i.targets.0 {
+            (d + swing, d + 2.0 * swing)
+        } else if i.sensor_temp > i.targets.1 {
+            (d - 2.0 * swing, d - swing)
+        } else {
+            (d - swing, d + swing)
+        };
+        /* an odd Daikin reading must not push the setpoints past the sane range */
+        Ok(Action::Set((sp.0.clamp(MIN_TARGET_TEMP, MAX_TARGET_TEMP - MIN_TARGET_GAP),
+            sp.1.clamp(MIN_TARGET_TEMP + MIN_TARGET_GAP, MAX_TARGET_TEMP))))
+    }
+}
+
+struct AutoTune {
+    swing: f64,
+}
+
+impl AutoTune {
+    /**
+     * advances the relay test with the reading `x` against target `t` (both negated when
+     * cooling, so a low value calls for the equipment), returning whether the relay is on,
+     * or None if the test is over
+     */
+    fn step(&self, at: &mut Autotune, x: f64, t: f64, now: i64) -> Option<bool> {
+        if now - at.started > AUTOTUNE_MAX_HOURS * 3600 {
+            print_event(&EventLog::warning(format!("PID autotune did not finish in {} hours, abandoned", AUTOTUNE_MAX_HOURS)));
+            at.done = true;
+            return None;
+        }
+        if at.first_on.is_some() && (x - t).abs() > AUTOTUNE_ABORT {
+            print_event(&EventLog::warning(format!("PID autotune abandoned: temperature strayed more than {} degrees from the target",
+                AUTOTUNE_ABORT)));
+            at.done = true;
+            return None;
+        }
+        let was_on = at.relay_on;
+        if at.relay_on && x >= t + AUTOTUNE_HYSTERESIS {
+            at.relay_on = false;
+        } else if !at.relay_on && x <= t - AUTOTUNE_HYSTERESIS {
+            at.relay_on = true;
+        }
+        at.high = at.high.max(x);
+        at.low = at.low.min(x);
+        if at.relay_on && !was_on {
+            if at.last_on.is_some() {
+                at.cycles += 1;
+                at.amplitude_sum += (at.high - at.low) / 2.0;
+            } else {
+                at.first_on = Some(now);
+            }
+            at.last_on = Some(now);
+            at.high = x;
+            at.low = x;
+        }
+        Some(at.relay_on)
+    }
+
+    /**
+     * returns gains from the oscillations of a finished test: Ziegler-Nichols' "no overshoot"
+     * rule, with the ultimate gain of a relay of amplitude `swing`
+     */
+    fn gains(&self, at: &Autotune) -> Option<Gains> {
+        let amplitude = at.amplitude_sum / at.cycles as f64;
+        let period = (at.last_on? - at.first_on?) as f64 / 60.0 / at.cycles as f64;
+        if amplitude <= 0.0 || period <= 0.0 {
+            return None;
+        }
+        let ku = 4.0 * self.swing / (std::f64::consts::PI * amplitude);
+        Some(Gains { kp: 0.2 * ku, ki: 0.4 * ku / period, kd: 0.066 * ku * period })
+    }
+}
+
+impl ControlPolicy for AutoTune {
+    fn decide(&self, i: &Inputs, state: &mut PolicyState) -> Result<Action, Error> {
+        if state.autotune.as_ref().is_some_and(|a| a.done) {
+            return Offset.decide(i, state);
+        }
+        let at = state.autotune.get_or_insert(Autotune {
+            started: i.now,
+            heating: i.outdoor_temp < i.targets.0,
+            relay_on: false,
+            first_on: None,
+            last_on: None,
+            cycles: 0,
+            high: f64::MIN,
+            low: f64::MAX,
+            amplitude_sum: 0.0,
+            done: false,
+        });
+        let (x, t) = if at.heating { (i.sensor_temp, i.targets.0) } else { (-i.sensor_temp, -i.targets.1) };
+        let on = match self.step(at, x, t, i.now) {
+            Some(on) => on,
+            None => return Offset.decide(i, state),
+        };
+        let heating = at.heating;
+        if at.cycles >= AUTOTUNE_CYCLES {
+            at.done = true;
+            match self.gains(at) {
+                Some(g) => {
+                    print_event(&EventLog::info(format!("PID autotune finished: suggested pid_kp = {:.3}, pid_ki = {:.4}, pid_kd = {:.2}",
+                        g.kp, g.ki, g.kd)));
+                    state.tuned = Some(g);
+                },
+                None => print_event(&EventLog::warning("PID autotune saw no oscillation, no gains suggested".to_string())),
+            }
+            return Offset.decide(i, state);
+        }
+        let (d, swing) = (i.daikin_temp, self.swing);
+        let sp = match (on, heating) {
+            (true, true) => (d + swing, d + 2.0 * swing),
+            (true, false) => (d - 2.0 * swing, d - swing),
+            (false, _) => (d - swing, d + swing),
+        };
+        let (heat, cool) = i.targets;
+        Ok(Action::Set((sp.0.clamp(heat - AUTOTUNE_CLAMP, heat + AUTOTUNE_CLAMP), sp.1.clamp(cool - AUTOTUNE_CLAMP, cool + AUTOTUNE_CLAMP))))
+    }
+}
+
+#[cfg(feature = "script")]
+struct Script<'a> {
+    path: &'a str,
+    config: &'a Config,
+}
+
+#[cfg(feature = "script")]
+impl ControlPolicy for Script<'_> {
+    fn decide(&self, i: &Inputs, _: &mut PolicyState) -> Result<Action, Error> {
+        let inputs = script::ScriptInputs {
+            sensor_temp: i.sensor_temp,
+            daikin_temp: i.daikin_temp,
+            outdoor_temp: i.outdoor_temp,
+            targets: i.targets,
+            current: i.current,
+            readings: i.readings,
+        };
+        Ok(Action::Set(script::setpoints(self.path, self.config, &inputs)?))
+    }
+}
+
+/* names accepted for `policy` */
+pub(crate) const POLICIES: &[&str] = &["offset", "deadband", "pid", "bangbang", "autotune", "script"];
+
+/* keys allowed in the [shadow] table */
+pub(crate) const SETTINGS: &[&str] = &["policy", "policy_deadband", "pid_kp", "pid_ki", "pid_kd", "bangbang_swing", "policy_script"];
+
+/**
+ * returns the policy named `name`, with parameters from the config
+ */
+pub(crate) fn select<'a>(name: &str, config: &'a Config) -> Box<dyn ControlPolicy + 'a> {
+    match name {
+        "deadband" => Box::new(Deadband { width: config.policy_deadband }),
+        "pid" => Box::new(Pid { kp: config.pid_kp, ki: config.pid_ki, kd: config.pid_kd }),
+        "bangbang" => Box::new(BangBang { swing: config.bangbang_swing }),
+        "autotune" => Box::new(AutoTune { swing: config.bangbang_swing }),
+        #[cfg(feature = "script")]
+        "script" => Box::new(Script { path: config.policy_script.as_deref().unwrap_or_default(), config }),
+        _ => Box::new(Offset),
+    }
+}
+
+/**
+ * returns the candidates `--tune` compares: every policy with a range of its parameters
+ */
+pub(crate) fn sweep() -> Vec<(String, Box<dyn ControlPolicy>)> {
+    let mut candidates: Vec<(String, Box<dyn ControlPolicy>)> = vec![("offset".to_string(), Box::new(Offset))];
+    for width in [0.25, 0.5, 1.0, 1.5] {
+        candidates.push((format!("deadband policy_deadband={}", width), Box::new(Deadband { width })));
+    }
+    for kp in [0.25, 0.5, 1.0] {
+        for ki in [0.0, 0.01, 0.05] {
+            for kd in [0.0, 5.0] {
+                candidates.push((format!("pid pid_kp={} pid_ki={} pid_kd={}", kp, ki, kd), Box::new(Pid { kp, ki, kd })));
+            }
+        }
+    }
+    for swing in [1.0, 2.0, 3.0] {
+        candidates.push((format!("bangbang bangbang_swing={}", swing), Box::new(BangBang { swing })));
+    }
+    candidates
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn policies() {
+        let mut i = Inputs {
+            sensor_temp: 20.0,
+            daikin_temp: 21.0,
+            outdoor_temp: 5.0,
+            targets: (21.0, 26.0),
+            current: (22.0, 27.0),
+            #[cfg(feature = "script")]
+            readings: Readings { temp: 20.0, humid: None, co2: None, pm25: None, voc: None },
+            now: 0,
+        };
+        let mut state = PolicyState::default();
+        assert_eq!(Offset.decide(&i, &mut state).unwrap(), Action::Set((22.0, 27.0)));
+        let deadband = Deadband { width: 0.5 };
+        assert_eq!(deadband.decide(&i, &mut state).unwrap(), Action::Keep);
+        i.current = (21.0, 27.0);
+        assert_eq!(deadband.decide(&i, &mut state).unwrap(), Action::Set((22.0, 27.0)));
+
+        assert_eq!(comfort_error(20.0, (21.0, 26.0)), 1.0);
+        assert_eq!(comfort_error(23.0, (21.0, 26.0)), 0.0);
+        assert_eq!(comfort_error(27.0, (21.0, 26.0)), -1.0);
+        let pid = Pid { kp: 0.5, ki: 0.1, kd: 0.0 };
+        assert_eq!(pid.decide(&i, &mut state).unwrap(), Action::Set((22.5, 27.5)));
+        /* still 1 degree short after 10 minutes: the integral adds up */
+        i.now = 600;
+        assert_eq!(pid.decide(&i, &mut state).unwrap(), Action::Set((23.5, 28.5)));
+
+        let bangbang = BangBang { swing: 3.0 };
+        assert_eq!(bangbang.decide(&i, &mut state).unwrap(), Action::Set((24.0, 27.0)));
+        i.sensor_temp = 23.0;
+        assert_eq!(bangbang.decide(&i, &mut state).unwrap(), Action::Set((18.0, 24.0)));
+        i.sensor_temp = 27.0;
+        assert_eq!(bangbang.decide(&i, &mut state).unwrap(), Action::Set((15.0, 18.0)));
+        /* the smallest swing still keeps the neutral setpoints MIN_TARGET_GAP apart */
+        let bangbang = BangBang { swing: MIN_TARGET_GAP / 2.0 };
+        i.sensor_temp = 23.0;
+        assert_eq!(bangbang.decide(&i, &mut state).unwrap(), Action::Set((20.5, 21.5)));
+        /* setpoints stay within the sane range */
+        let bangbang = BangBang { swing: 3.0 };
+        i.daikin_temp = 31.0;
+        i.sensor_temp = 20.0;
+        assert_eq!(bangbang.decide(&i, &mut state).unwrap(), Action::Set((MAX_TARGET_TEMP - MIN_TARGET_GAP, MAX_TARGET_TEMP)));
+        i.daikin_temp = 11.0;
+        i.sensor_temp = 27.0;
+        assert_eq!(bangbang.decide(&i, &mut state).unwrap(), Action::Set((MIN_TARGET_TEMP, MIN_TARGET_TEMP + MIN_TARGET_GAP)));
+        i.daikin_temp = 21.0;
+
+        /* oscillation of 0.5 degrees around the heating target with a period of 60 minutes */
+        let autotune = AutoTune { swing: 3.0 };
+        let mut state = PolicyState::default();
+        let wave = [21.5, 21.1, 20.9, 20.5, 20.9, 21.1];
+        for n in 0..22 {
+            i.sensor_temp = wave[n % wave.len()];
+            i.now = n as i64 * 600;
+            autotune.decide(&i, &mut state).unwrap();
+        }
+        let g = state.tuned.unwrap();
+        let ku = 12.0 / (std::f64::consts::PI * 0.5);
+        assert!((g.kp - 0.2 * ku).abs() < 1e-9);
+        assert!((g.ki - 0.4 * ku / 60.0).abs() < 1e-9);
+        assert!(state.autotune.unwrap().done);
+    }
+}
@@ -0,0 +1,219 @@
+/*
+ * Day-of-week profiles: bundles of targets and control window selected by day.
+ *
+ *   [profiles.weekend]
+ *   target_temp_heat = 22.0
+ *   control_start = '23:00'
+ *   control_end = '09:00'
+ *
+ *   [profile_days]
+ *   sat = 'weekend'
+ *   sun = 'weekend'
+ *
+ * Keys omitted in a profile, and days without a profile, use the top-level settings.
+ * A window crossing midnight belongs to the day it starts on.
+ */
+use chrono::{Datelike, NaiveDateTime, Weekday};
+use serde::{Deserialize, Serialize};
+use super::*;
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct Profile {
+    pub target_temp_heat: Option<f64>,
+    pub target_temp_cool: Option<f64>,
+    pub control_start: Option<String>,
+    pub control_end: Option<String>,
+}
+
+/* effective settings for one control window */
+pub(crate) struct Settings<'a> {
+    pub(crate) profile: Option<&'a str>,
+    pub(crate) target_heat: f64,
+    pub(crate) target_cool: f64,
+    pub(crate) range: TimeRange,
+}
+
+fn profile_name(config: &Config, day: Weekday) -> Option<&str> {
+    config.profile_days.iter()
+        .find(|(d, _)| d.parse::<Weekday>().ok() == Some(day))
+        .map(|(_, p)| p.as_str())
+}
+
+/**
+ * returns settings for the control window starting on the given day
+ */
+pub(crate) fn settings_for_day(config: &Config, day: Weekday) -> Settings<'_> {
+    let name = profile_name(config, day);
+    let profile = name.and_then(|n| config.profiles.get(n)).cloned().unwrap_or_default();
+    let start = profile.control_start.as_ref().unwrap_or(&config.control_start);
+    let end = profile.control_end.as_ref().unwrap_or(&config.control_end);
+    Settings {
+        profile: name,
+        target_heat: profile.target_temp_heat.unwrap_or(config.target_temp_heat),
+        target_cool: profile.target_temp_cool.unwrap_or(config.target_temp_cool),
+        range: parse_time_range(start, end),
+    }
+}
+
+/**
+ * returns settings of the control window containing `now`, or None outside any window
+ */
+pub(crate) fn active_settings<'a>(config: &'a Config, now: &NaiveDateTime) -> Option<Settings<'a>> {
+    let t = now.time();
+    /* early-morning part of a window that started yesterday */
+    let yesterday = settings_for_day(config, now.weekday().pred());
+    if let TimeRange::Split(end, _) = yesterday.range {
+        if t <= end {
+            return Some(yesterday);
+        }
+    }
+    let today = settings_for_day(config, now.weekday());
+    let inside = match today.range {
+        TimeRange::Contiguous(..) => today.range.contains(&t),
+        TimeRange::Split(_, begin) => begin <= t,
+    };
+    if inside {
+        Some(today)
+    } else {
+        None
+    }
+}
+
+/**
+ * returns when the control window containing `now` ends, or None outside any window
+ */
+pub(crate) fn window_end(config: &Config, now: &NaiveDateTime) -> Option<NaiveDateTime> {
+    let end = match active_settings(config, now)?.range {
+        TimeRange::Contiguous(_, end) => now.date().and_time(end),
+        /* a window that started yesterday ends today, one that started today ends tomorrow */
+        TimeRange::Split(end, _) if now.time() <= end => now.date().and_time(end),
+        TimeRange::Split(end, _) => now.date().succ_opt()?.and_time(end),
+    };
+    Some(end)
+}
+
+/**
+ * returns the ranges that may have a boundary within the next day; the earliest
+ * transition among them is never later than the real next transition
+ */
+pub(crate) fn candidate_ranges(config: &Config, now: &NaiveDateTime) -> Vec<TimeRange> {
+    let day = now.weekday();
+    vec![
+        settings_for_day(config, day.pred()).range,
+        settings_for_day(config, day).range,
+        settings_for_day(config, day.succ()).range,
+    ]
+}
+
+/**
+ * checks profiles and the day selector, reporting problems as (key, message)
+ */
+pub fn validate(config: &Config) -> Vec<(String, String)> {
+    let mut problems = Vec::new();
+    for (day, name) in config.profile_days.iter() {
+        if day.parse::<Weekday>().is_err() {
+            problems.push((day.clone(), "is not a day of week (mon, tue, ...)".to_string()));
+        }
+        if !config.profiles.contains_key(name) {
+            problems.push((day.clone(), format!("profile \"{}\" is not defined", name)));
+        }
+    }
+    for (name, p) in config.profiles.iter() {
+        for (key, value) in [("control_start", &p.control_start), ("control_end", &p.control_end)] {
+            if let Some(v) = value {
+                if NaiveTime::parse_from_str(v, "%R").is_err() {
+                    problems.push((key.to_string(), format!("in profile \"{}\" must be in HH:MM format: \"{}\"", name, v)));
+                }
+            }
+        }
+        let heat = p.target_temp_heat.unwrap_or(config.target_temp_heat);
+        let cool = p.target_temp_cool.unwrap_or(config.target_temp_cool);
+        if heat > cool {
+            problems.push(("target_temp_heat".to_string(),
+                format!("in profile \"{}\" must be lower than or equal to target_temp_cool", name)));
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn weekend_profile() {
+        let config: Config = toml::from_str(r#"
+            awair_token = "token"
+            target_temp_heat = 21.0
+            target_temp_cool = 26.0
+            control_start = "21:00"
+            control_end = "07:00"
+            daikin_email = "daikin@example.com"
+            daikin_password = "secret"
+
+            [profiles.weekend]
+            target_temp_heat = 22.0
+            control_start = "23:00"
+            control_end = "09:00"
+
+            [profile_days]
+            sat = "weekend"
+            sun = "weekend"
+        "#).unwrap();
+        assert!(validate(&config).is_empty());
+        let at = |s: &str| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap();
+        /* Friday night uses the default window */
+        let s = active_settings(&config, &at("2022-03-18 22:00")).unwrap();
+        assert!(s.profile.is_none());
+        assert_eq!(s.target_heat, 21.0);
+        /* Friday's window ends at 07:00 on Saturday */
+        assert!(active_settings(&config, &at("2022-03-19 08:00")).is_none());
+        /* Saturday 22:00 is before the weekend window */
+        assert!(active_settings(&config, &at("2022-03-19 22:00")).is_none());
+        let s = active_settings(&config, &at("2022-03-19 23:30")).unwrap();
+        assert_eq!(s.profile, Some("weekend"));
+        assert_eq!(s.target_heat, 22.0);
+        assert_eq!(s.target_cool, 26.0);
+        /* Monday 08:00 belongs to Sunday's weekend window */
+        let s = active_settings(&config, &at("2022-03-21 08:00")).unwrap();
+        assert_eq!(s.profile, Some("weekend"));
+        /* Monday 21:30 is a weekday window again */
+        assert!(active_settings(&config, &at("2022-03-21 21:30")).unwrap().profile.is_none());
+    }
+
+    #[test]
+    fn window_ends() {
+        let config: Config = toml::from_str(r#"
+            awair_token = "token"
+            target_temp_heat = 21.0
+            target_temp_cool = 26.0
+            control_start = "21:00"
+            control_end = "07:00"
+            daikin_email = "daikin@example.com"
+            daikin_password = "secret"
+
+            [profiles.early]
+            control_start = "20:00"
+            control_end = "05:30"
+
+            [profiles.weekend]
+            control_start = "23:00"
+            control_end = "09:00"
+
+            [profile_days]
+            fri = "early"
+            sat = "weekend"
+        "#).unwrap();
+        assert!(validate(&config).is_empty());
+        let at = |s: &str| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap();
+        /* Thursday's default window, although Friday's starts at a different time */
+        assert_eq!(window_end(&config, &at("2022-03-18 04:00")), Some(at("2022-03-18 07:00")));
+        /* Friday's early window ends Saturday at 05:30, not at the weekend's 09:00 */
+        assert_eq!(window_end(&config, &at("2022-03-18 20:30")), Some(at("2022-03-19 05:30")));
+        assert_eq!(window_end(&config, &at("2022-03-19 05:00")), Some(at("2022-03-19 05:30")));
+        assert_eq!(window_end(&config, &at("2022-03-19 06:00")), None);
+        /* Saturday's weekend window ends Sunday at 09:00, not at Sunday's 21:00 start */
+        assert_eq!(window_end(&config, &at("2022-03-19 23:30")), Some(at("2022-03-20 09:00")));
+        assert_eq!(window_end(&config, &at("2022-03-20 08:00")), Some(at("2022-03-20 09:00")));
+    }
+}
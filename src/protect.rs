@@ -0,0 +1,83 @@
+/*
+ * Compressor protection: minimum spacing between setpoint changes that start the
+ * equipment again or reverse its direction (heat <-> cool).
+ *
+ * The demand implied by setpoints is judged against Daikin's own temperature reading,
+ * so the guard is independent of how targets and corrections were computed.
+ */
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub enum Demand {
+    #[default]
+    Idle,
+    Heat,
+    Cool,
+}
+
+/**
+ * returns what the setpoints ask the equipment to do at Daikin temperature `dtemp`
+ */
+pub(crate) fn demand(hsp: f64, csp: f64, dtemp: f64) -> Demand {
+    if hsp > dtemp {
+        Demand::Heat
+    } else if csp < dtemp {
+        Demand::Cool
+    } else {
+        Demand::Idle
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct CycleGuard {
+    /* demand of the setpoints applied last */
+    demand: Demand,
+    /* unix time the demand last changed */
+    since: Option<i64>,
+}
+
+impl CycleGuard {
+    /**
+     * returns true if setpoints with demand `next` may be applied now.
+     * Going idle is always allowed; starting or reversing needs `spacing` minutes since the last change.
+     */
+    pub(crate) fn allows(&self, next: Demand, now: i64, spacing: u32) -> bool {
+        if next == self.demand || next == Demand::Idle {
+            return true;
+        }
+        match self.since {
+            Some(since) => now - since >= spacing as i64 * 60,
+            None => true,
+        }
+    }
+
+    pub(crate) fn record(&mut self, next: Demand, now: i64) {
+        if next != self.demand {
+            self.demand = next;
+            self.since = Some(now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cycle_spacing() {
+        assert_eq!(demand(22.0, 26.0, 21.0), Demand::Heat);
+        assert_eq!(demand(20.0, 26.0, 21.0), Demand::Idle);
+        assert_eq!(demand(18.0, 20.0, 21.0), Demand::Cool);
+
+        let mut guard = CycleGuard::default();
+        assert!(guard.allows(Demand::Heat, 0, 10));
+        guard.record(Demand::Heat, 0);
+        /* reversing right away is deferred */
+        assert!(!guard.allows(Demand::Cool, 5 * 60, 10));
+        assert!(guard.allows(Demand::Idle, 5 * 60, 10));
+        guard.record(Demand::Idle, 5 * 60);
+        /* so is starting again right after stopping */
+        assert!(!guard.allows(Demand::Heat, 10 * 60, 10));
+        assert!(guard.allows(Demand::Heat, 15 * 60, 10));
+    }
+}
@@ -0,0 +1,246 @@
+/*
+ * HTTP ingestion of readings pushed by external sensors (`push_listen`), for homes with
+ * `sensor = "push"`. A sensor posts its readings for a zone:
+ *
+ *   POST /readings/<zone>
+ *   Authorization: Bearer <push_token>
+ *
+ *   {"temp": 21.3, "humid": 45.0}
+ *
+ * temp is required; humid, co2, pm25 and voc are optional. A home reads the zone named
+ * `push_zone` (default: the home name, or "default"), and a zone without a reading for
+ * `push_max_age` minutes is reported offline.
+ */
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use super::*;
+
+/* largest request body accepted */
+const MAX_BODY: usize = 4096;
+/* largest request line and headers accepted, together */
+const MAX_HEADER: u64 = 8192;
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/* latest (unix time, reading) per zone */
+static READINGS: Mutex<Option<HashMap<String, (i64, Readings)>>> = Mutex::new(None);
+
+struct Request {
+    method: String,
+    path: String,
+    token: Option<String>,
+    body: Vec<u8>,
+}
+
+/**
+ * reads a line of the request head, taking its length from the `budget` left of MAX_HEADER
+ */
+async fn read_head_line(reader: &mut BufReader<&mut TcpStream>, budget: &mut u64) -> Result<String, u32> {
+    let mut line = String::new();
+    let n = reader.take(*budget).read_line(&mut line).await.map_err(|_| 400u32)?;
+    *budget -= n as u64;
+    match line.ends_with('\n') {
+        true => Ok(line),
+        false if *budget == 0 => Err(431),
+        /* connection closed mid-line */
+        false => Err(400),
+    }
+}
+
+/**
+ * reads a request, or returns the HTTP status to reject it with
+ */
+async fn read_request(stream: &mut TcpStream) -> Result<Request, u32> {
+    let mut reader = BufReader::new(stream);
+    let mut budget = MAX_HEADER;
+    let line = read_head_line(&mut reader, &mut budget).await?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next().ok_or(400u32)?.to_string();
+    let path = parts.next().ok_or(400u32)?.to_string();
+    let mut length = 0;
+    let mut token = None;
+    loop {
+        let header = read_head_line(&mut reader, &mut budget).await?;
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            let value = value.trim();
+            match name.trim().to_ascii_lowercase().as_str() {
+                "content-length" => length = value.parse().map_err(|_| 400u32)?,
+                "authorization" => token = value.strip_prefix("Bearer ").map(|t| t.to_string()),
+                _ => (),
+            }
+        }
+    }
+    if length > MAX_BODY {
+        return Err(400);
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).await.map_err(|_| 400u32)?;
+    Ok(Request { method, path, token, body })
+}
+
+/**
+ * compares a bearer token with the configured one in time independent of where they differ
+ */
+fn token_matches(given: &str, token: &str) -> bool {
+    given.len() == token.len() && given.bytes().zip(token.bytes()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/**
+ * handles a request, returning the HTTP status to answer with
+ */
+fn handle(req: &Request, token: &str, now: i64) -> u32 {
+    let zone = match req.path.strip_prefix("/readings/") {
+        Some(z) if !z.is_empty() && !z.contains('/') => z,
+        _ => return 404,
+    };
+    if req.method != "POST" {
+        return 405;
+    }
+    if !req.token.as_deref().is_some_and(|t| token_matches(t, token)) {
+        return 401;
+    }
+    let readings: Readings = match serde_json::from_slice(&req.body) {
+        Ok(r) => r,
+        Err(_) => return 400,
+    };
+    let mut map = READINGS.lock().unwrap_or_else(|e| e.into_inner());
+    map.get_or_insert_with(HashMap::new).insert(zone.to_string(), (now, readings));
+    204
+}
+
+async fn respond(stream: &mut TcpStream, status: u32) {
+    let reason = match status {
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        431 => "Request Header Fields Too Large",
+        _ => "Error",
+    };
+    let response = format!("HTTP/1.1 {} {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status, reason);
+    let _ = stream.write_all(response.as_bytes()).await;
+}
+
+/**
+ * starts accepting pushed readings on `addr` in a background task, with a task per
+ * connection so a slow client does not hold up the others
+ */
+pub async fn serve(addr: &str, token: &str) -> Result<(), Error> {
+    let listener = TcpListener::bind(addr).await?;
+    let token = Arc::new(token.to_string());
+    tokio::spawn(async move {
+        loop {
+            let mut stream = match listener.accept().await {
+                Ok((s, _)) => s,
+                Err(_) => continue,
+            };
+            let token = Arc::clone(&token);
+            tokio::spawn(async move {
+                let status = match tokio::time::timeout(READ_TIMEOUT, read_request(&mut stream)).await {
+                    Ok(Ok(req)) => handle(&req, &token, Utc::now().timestamp()),
+                    Ok(Err(status)) => status,
+                    Err(_) => 400,
+                };
+                respond(&mut stream, status).await;
+            });
+        }
+    });
+    Ok(())
+}
+
+/**
+ * returns the zone read by a home
+ */
+pub(crate) fn zone(config: &Config) -> &str {
+    config.push_zone.as_deref().or(config.name.as_deref()).unwrap_or("default")
+}
+
+/**
+ * returns the latest reading pushed for the home's zone, if recent enough
+ */
+pub(crate) fn latest(config: &Config, now: i64) -> Result<Readings, Error> {
+    let zone = zone(config);
+    let map = READINGS.lock().unwrap_or_else(|e| e.into_inner());
+    let offline = |message: String| Error::Offline { device: format!("push sensor \"{}\"", zone), message };
+    match map.as_ref().and_then(|m| m.get(zone)) {
+        Some((at, _)) if now - at > config.push_max_age as i64 * 60 =>
+            Err(offline(format!("no reading for {} minutes", (now - at) / 60))),
+        Some((_, r)) => Ok(*r),
+        None => Err(offline("no reading received yet".to_string())),
+    }
+}
+
+/* the home's zone as a TemperatureSensor */
+pub(crate) struct PushSensor<'a> {
+    pub(crate) config: &'a Config,
+}
+
+#[async_trait::async_trait]
+impl TemperatureSensor for PushSensor<'_> {
+    async fn read(&self) -> Result<Readings, Error> {
+        latest(self.config, Utc::now().timestamp())
+    }
+
+    fn endpoint(&self) -> &'static str {
+        "push"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn push_readings() {
+        let req = |method: &str, path: &str, token: Option<&str>, body: &str| Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            token: token.map(|t| t.to_string()),
+            body: body.as_bytes().to_vec(),
+        };
+        let body = r#"{"temp": 21.5, "humid": 40.0}"#;
+        assert_eq!(handle(&req("POST", "/readings/test-zone", Some("secret"), body), "secret", 1000), 204);
+        assert_eq!(handle(&req("POST", "/readings/test-zone", Some("wrong"), body), "secret", 1000), 401);
+        assert_eq!(handle(&req("POST", "/readings/test-zone", Some("secreT"), body), "secret", 1000), 401);
+        assert_eq!(handle(&req("POST", "/readings/test-zone", None, body), "secret", 1000), 401);
+        assert!(token_matches("secret", "secret"));
+        assert!(!token_matches("secret2", "secret"));
+        assert!(!token_matches("", "secret"));
+        assert_eq!(handle(&req("POST", "/readings/test-zone", Some("secret"), "{}"), "secret", 1000), 400);
+        assert_eq!(handle(&req("GET", "/readings/test-zone", Some("secret"), ""), "secret", 1000), 405);
+        assert_eq!(handle(&req("POST", "/other", Some("secret"), body), "secret", 1000), 404);
+
+        let mut config = crate::test::test_config();
+        config.push_zone = Some("test-zone".to_string());
+        assert_eq!(latest(&config, 1000 + 60).unwrap().humid, Some(40.0));
+        assert!(matches!(latest(&config, 1000 + 60 * 60), Err(Error::Offline { .. })));
+        config.push_zone = Some("unknown".to_string());
+        assert!(latest(&config, 1000).is_err());
+    }
+    #[tokio::test]
+    async fn request_head_limit() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let send = |head: String| async move {
+            let mut client = TcpStream::connect(addr).await.unwrap();
+            client.write_all(head.as_bytes()).await.unwrap();
+            client
+        };
+        let body = r#"{"temp": 21.5}"#;
+        let _client = send(format!("POST /readings/z HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}", body.len(), body)).await;
+        let req = read_request(&mut listener.accept().await.unwrap().0).await.ok().unwrap();
+        assert_eq!((req.path.as_str(), req.body.as_slice()), ("/readings/z", body.as_bytes()));
+        let _client = send(format!("POST /readings/z HTTP/1.1\r\nX-Padding: {}\r\n\r\n", "a".repeat(MAX_HEADER as usize))).await;
+        assert_eq!(read_request(&mut listener.accept().await.unwrap().0).await.err(), Some(431));
+        /* closed before the end of the headers */
+        drop(send("POST /readings/z HTTP/1.1\r\nContent-".to_string()).await);
+        assert_eq!(read_request(&mut listener.accept().await.unwrap().0).await.err(), Some(400));
+    }
+}
@@ -0,0 +1,39 @@
+/*
+ * Gradual setpoint changes around the control window.
+ *
+ * At the window start, setpoints move from what Daikin One+ had when the window began
+ * to the corrected setpoints over `ramp_start` minutes, instead of jumping at once.
+ * Likewise, during the last `ramp_end` minutes of the window they move back toward
+ * what the native schedule wants at the window end, so the override does not expire abruptly.
+ */
+
+/**
+ * returns how far (0.0 - 1.0) a ramp of `period` minutes has progressed after `elapsed` seconds
+ */
+pub(crate) fn progress(elapsed: i64, period: u32) -> f64 {
+    if period == 0 {
+        return 1.0;
+    }
+    (elapsed as f64 / (period as f64 * 60.0)).clamp(0.0, 1.0)
+}
+
+/**
+ * returns (heat, cool) setpoints `f` of the way from `from` to `to`
+ */
+pub(crate) fn blend(from: (f64, f64), to: (f64, f64), f: f64) -> (f64, f64) {
+    (from.0 + (to.0 - from.0) * f, from.1 + (to.1 - from.1) * f)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ramp_progress() {
+        assert_eq!(progress(0, 30), 0.0);
+        assert_eq!(progress(15 * 60, 30), 0.5);
+        assert_eq!(progress(60 * 60, 30), 1.0);
+        assert_eq!(progress(0, 0), 1.0);
+        assert_eq!(blend((20.0, 26.0), (22.0, 24.0), 0.5), (21.0, 25.0));
+    }
+}
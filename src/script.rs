@@ -0,0 +1,138 @@
+/*
+ * Script policy (`policy = "script"`, needs the "script" feature): the setpoints are computed
+ * by a Rhai function in `policy_script`:
+ *
+ *   fn setpoints(r, config) {
+ *       let offset = r.sensor_temp - r.daikin_temp;
+ *       #{ heat: r.target_heat - offset, cool: r.target_cool - offset }
+ *   }
+ *
+ * `r` holds sensor_temp (the temperature controlled on), daikin_temp, outdoor_temp,
+ * target_heat, target_cool, heat_setpoint and cool_setpoint (Daikin's current setpoints),
+ * and humid, co2, pm25 and voc (() if not reported). `config` holds all configuration values.
+ * The result must be finite, within the sane target range and MIN_TARGET_GAP apart, or the
+ * cycle falls back to the offset policy. Scripts are compiled once per path. A call may run at
+ * most MAX_OPERATIONS operations and nest MAX_CALL_LEVELS function calls deep, so a runaway
+ * script fails the cycle instead of hanging it.
+ */
+use rhai::{Dynamic, Engine, Map, AST};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use super::*;
+
+/* a compiled script and the engine it runs on */
+type Compiled = Arc<(Engine, AST)>;
+
+const MAX_OPERATIONS: u64 = 1_000_000;
+const MAX_CALL_LEVELS: usize = 32;
+
+static SCRIPTS: Mutex<Option<HashMap<String, Compiled>>> = Mutex::new(None);
+
+/* inputs to a script */
+pub(crate) struct ScriptInputs {
+    pub(crate) sensor_temp: f64,
+    pub(crate) daikin_temp: f64,
+    pub(crate) outdoor_temp: f64,
+    pub(crate) targets: (f64, f64),
+    pub(crate) current: (f64, f64),
+    pub(crate) readings: Readings,
+}
+
+fn script_error(e: impl std::fmt::Display) -> Error {
+    Error::Config(format!("policy_script: {}", e))
+}
+
+fn load(path: &str) -> Result<Compiled, Error> {
+    let mut scripts = SCRIPTS.lock().unwrap_or_else(|e| e.into_inner());
+    let scripts = scripts.get_or_insert_with(HashMap::new);
+    if let Some(s) = scripts.get(path) {
+        return Ok(s.clone());
+    }
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine.set_max_call_levels(MAX_CALL_LEVELS);
+    let ast = engine.compile_file(path.into()).map_err(script_error)?;
+    let compiled = Arc::new((engine, ast));
+    scripts.insert(path.to_string(), compiled.clone());
+    Ok(compiled)
+}
+
+fn optional(v: Option<f64>) -> Dynamic {
+    v.map_or(Dynamic::UNIT, Dynamic::from_float)
+}
+
+/**
+ * returns (heat, cool) setpoints computed by the script at `path`
+ */
+pub(crate) fn setpoints(path: &str, config: &Config, inputs: &ScriptInputs) -> Result<(f64, f64), Error> {
+    let compiled = load(path)?;
+    let (engine, ast) = &*compiled;
+    let mut r = Map::new();
+    r.insert("sensor_temp".into(), Dynamic::from_float(inputs.sensor_temp));
+    r.insert("daikin_temp".into(), Dynamic::from_float(inputs.daikin_temp));
+    r.insert("outdoor_temp".into(), Dynamic::from_float(inputs.outdoor_temp));
+    r.insert("target_heat".into(), Dynamic::from_float(inputs.targets.0));
+    r.insert("target_cool".into(), Dynamic::from_float(inputs.targets.1));
+    r.insert("heat_setpoint".into(), Dynamic::from_float(inputs.current.0));
+    r.insert("cool_setpoint".into(), Dynamic::from_float(inputs.current.1));
+    r.insert("humid".into(), optional(inputs.readings.humid));
+    r.insert("co2".into(), optional(inputs.readings.co2));
+    r.insert("pm25".into(), optional(inputs.readings.pm25));
+    r.insert("voc".into(), optional(inputs.readings.voc));
+    let config = rhai::serde::to_dynamic(config).map_err(script_error)?;
+    let result: Map = engine.call_fn(&mut rhai::Scope::new(), ast, "setpoints", (r, config)).map_err(|e| match *e {
+        rhai::EvalAltResult::ErrorTooManyOperations(_) =>
+            script_error(format!("setpoints() ran over {} operations", MAX_OPERATIONS)),
+        rhai::EvalAltResult::ErrorStackOverflow(_) =>
+            script_error(format!("setpoints() nested calls over {} levels deep", MAX_CALL_LEVELS)),
+        e => script_error(e),
+    })?;
+    let get = |key: &str| result.get(key).and_then(|v| v.as_float().ok())
+        .ok_or_else(|| script_error(format!("setpoints() must return #{{ heat: <float>, cool: <float> }}, missing {}", key)));
+    check_setpoints((get("heat")?, get("cool")?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn runaway_script() {
+        let path = std::env::temp_dir().join(format!("daikawa-script-test-{}.rhai", std::process::id()));
+        std::fs::write(&path, "fn deep(n) { deep(n + 1) }\nfn setpoints(r, config) { if r.humid == () { loop {} } deep(0) }\n").unwrap();
+        let inputs = |humid| ScriptInputs {
+            sensor_temp: 21.0,
+            daikin_temp: 22.0,
+            outdoor_temp: 10.0,
+            targets: (20.0, 26.0),
+            current: (20.0, 26.0),
+            readings: Readings { temp: 21.0, humid, co2: None, pm25: None, voc: None },
+        };
+        let config = crate::test::test_config();
+        let looping = setpoints(path.to_str().unwrap(), &config, &inputs(None));
+        let recursing = setpoints(path.to_str().unwrap(), &config, &inputs(Some(50.0)));
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(looping, Err(Error::Config(m)) if m.contains("operations")));
+        assert!(matches!(recursing, Err(Error::Config(m)) if m.contains("levels deep")));
+    }
+
+    #[test]
+    fn bad_setpoints() {
+        let path = std::env::temp_dir().join(format!("daikawa-setpoints-test-{}.rhai", std::process::id()));
+        std::fs::write(&path, "fn setpoints(r, config) { if r.humid == () { #{ heat: 26.0, cool: 20.0 } } else { #{ heat: 0.0 / 0.0, cool: 26.0 } } }\n").unwrap();
+        let inputs = |humid| ScriptInputs {
+            sensor_temp: 21.0,
+            daikin_temp: 22.0,
+            outdoor_temp: 10.0,
+            targets: (20.0, 26.0),
+            current: (20.0, 26.0),
+            readings: Readings { temp: 21.0, humid, co2: None, pm25: None, voc: None },
+        };
+        let config = crate::test::test_config();
+        let inverted = setpoints(path.to_str().unwrap(), &config, &inputs(None));
+        let nan = setpoints(path.to_str().unwrap(), &config, &inputs(Some(50.0)));
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(inverted, Err(Error::Invalid(m)) if m.contains("above heat")));
+        assert!(matches!(nan, Err(Error::Invalid(m)) if m.contains("not numbers")));
+    }
+}
@@ -0,0 +1,84 @@
+/*
+ * Automatic heating/cooling season detection for `mode = "season"`.
+ *
+ * The season follows the running mean of outdoor temperatures observed over the last
+ * `season_days` days: below `season_heat_below` is the heating season, above
+ * `season_cool_above` is the cooling season, and in between the previous season is kept.
+ * Alternatively `cooling_months` selects the season by date.
+ */
+use chrono::{Datelike, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use super::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub enum Season {
+    Heating,
+    Cooling,
+}
+
+impl Season {
+    fn mode(&self) -> ControlMode {
+        match self {
+            Season::Heating => ControlMode::Heat,
+            Season::Cooling => ControlMode::Cool,
+        }
+    }
+}
+
+/**
+ * returns the season for the given running mean (or date), keeping `prev` within the dead band
+ */
+fn detect(config: &Config, prev: Option<Season>, mean: Option<f64>, now: &NaiveDateTime) -> Option<Season> {
+    if let Some(months) = &config.cooling_months {
+        return Some(if months.contains(&now.month()) { Season::Cooling } else { Season::Heating });
+    }
+    match mean {
+        Some(m) if m < config.season_heat_below => Some(Season::Heating),
+        Some(m) if m > config.season_cool_above => Some(Season::Cooling),
+        _ => prev,
+    }
+}
+
+/**
+ * returns the control mode for the current season, judged from recorded outdoor temperatures.
+ * Until a season is determined, both setpoints are adjusted.
+ */
+pub(crate) fn update(config: &Config, state: &mut ControlState, now: i64) -> ControlMode {
+    let mean = state.outdoor_mean(now, config.season_days);
+    let season = detect(config, state.season, mean, &wall_clock(config));
+    if season != state.season {
+        let mean = mean.map_or("n/a".to_string(), |m| format!("{:.1}", m));
+        print_event(&EventLog::info(format!("season changed: {:?} -> {:?} (outdoor running mean {})",
+            state.season, season, mean)));
+        state.season = season;
+    }
+    season.map_or(ControlMode::Auto, |s| s.mode())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn season_hysteresis() {
+        let mut config: Config = toml::from_str(r#"
+            awair_token = "token"
+            target_temp_heat = 21.0
+            target_temp_cool = 26.0
+            control_start = "21:00"
+            control_end = "07:00"
+            daikin_email = "daikin@example.com"
+            daikin_password = "secret"
+            season_heat_below = 15.0
+            season_cool_above = 20.0
+        "#).unwrap();
+        let now = NaiveDateTime::parse_from_str("2022-05-01 22:00", "%Y-%m-%d %H:%M").unwrap();
+        assert_eq!(detect(&config, None, Some(17.0), &now), None);
+        assert_eq!(detect(&config, None, Some(10.0), &now), Some(Season::Heating));
+        assert_eq!(detect(&config, Some(Season::Heating), Some(17.0), &now), Some(Season::Heating));
+        assert_eq!(detect(&config, Some(Season::Heating), Some(22.0), &now), Some(Season::Cooling));
+
+        config.cooling_months = Some(vec![6, 7, 8]);
+        assert_eq!(detect(&config, Some(Season::Cooling), Some(30.0), &now), Some(Season::Heating));
+    }
+}
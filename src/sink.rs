@@ -0,0 +1,270 @@
+/*
+ * Output sinks for log records (TempLog and events).
+ *
+ * Records are always printed to stdout. Additional sinks (e.g. metrics servers)
+ * registered via `init` receive the same records on a thread of their own, so a slow or
+ * unreachable sink never holds up the control loop; when a sink is unreachable,
+ * records are queued in memory and flushed in order once it accepts records again,
+ * so a temporary network outage does not drop data.
+ * Records are redacted (see redact.rs) before they leave.
+ */
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
+use super::Error;
+
+#[cfg(feature = "prometheus")]
+pub mod pushgateway;
+#[cfg(feature = "graphite")]
+pub mod graphite;
+#[cfg(feature = "statsd")]
+pub mod statsd;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+
+pub trait Sink: Send {
+    fn name(&self) -> &str;
+    fn send(&mut self, record: &serde_json::Value) -> Result<(), Error>;
+}
+
+/* longest a sink may take to connect or send, so one that is down fails fast */
+#[cfg_attr(not(any(feature = "prometheus", feature = "graphite", feature = "postgres")), allow(dead_code))]
+pub const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/* how often queued records are retried while no new records arrive */
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+struct QueuedSink {
+    sink: Box<dyn Sink>,
+    queue: VecDeque<serde_json::Value>,
+    dropped: u64,
+}
+
+/* where records go to the dispatching thread, and that thread */
+struct Dispatcher {
+    records: Sender<serde_json::Value>,
+    thread: std::thread::JoinHandle<()>,
+}
+
+static DISPATCHER: Mutex<Option<Dispatcher>> = Mutex::new(None);
+
+thread_local! {
+    /* set on the dispatching thread, so records a sink causes (e.g. HTTP traces) only go to stdout */
+    static DISPATCHING: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+/**
+ * registers sinks that receive every record in addition to stdout.
+ * At most `queue_limit` records are kept per sink while it is unreachable.
+ */
+pub fn init(sinks: Vec<Box<dyn Sink>>, queue_limit: usize) {
+    if sinks.is_empty() {
+        return;
+    }
+    let sinks = sinks.into_iter().map(|sink| QueuedSink { sink, queue: VecDeque::new(), dropped: 0 }).collect();
+    let (records, rx) = std::sync::mpsc::channel();
+    let thread = std::thread::spawn(move || {
+        DISPATCHING.with(|d| d.set(true));
+        dispatch(rx, sinks, queue_limit, RETRY_INTERVAL);
+    });
+    if let Ok(mut d) = DISPATCHER.lock() {
+        *d = Some(Dispatcher { records, thread });
+    }
+}
+
+/**
+ * waits until records emitted so far have been offered to the sinks, for exiting without
+ * losing them
+ */
+pub fn finish() {
+    let dispatcher = DISPATCHER.lock().ok().and_then(|mut d| d.take());
+    if let Some(Dispatcher { records, thread }) = dispatcher {
+        drop(records);
+        let _ = thread.join();
+    }
+}
+
+/**
+ * forwards records from `rx` to the sinks until every sender is gone, retrying queued
+ * records every `retry` while none arrive
+ */
+fn dispatch(rx: Receiver<serde_json::Value>, mut sinks: Vec<QueuedSink>, limit: usize, retry: Duration) {
+    loop {
+        match rx.recv_timeout(retry) {
+            Ok(record) => for s in sinks.iter_mut() {
+                s.push(record.clone(), limit);
+            },
+            Err(RecvTimeoutError::Timeout) => for s in sinks.iter_mut() {
+                s.flush();
+            },
+            Err(RecvTimeoutError::Disconnected) => {
+                for s in sinks.iter_mut() {
+                    s.flush();
+                }
+                return;
+            },
+        }
+    }
+}
+
+impl QueuedSink {
+    fn flush(&mut self) {
+        while let Some(record) = self.queue.front() {
+            if let Err(e) = self.sink.send(record) {
+                if self.queue.len() == 1 {
+                    /* report only when a record gets queued first */
+                    eprintln!("Sink {} is unavailable, queueing records: {}", self.sink.name(), super::redact::text(&e.to_string()));
+                }
+                return;
+            }
+            self.queue.pop_front();
+        }
+        if self.dropped > 0 {
+            eprintln!("Sink {} is back, {} records were dropped while unavailable", self.sink.name(), self.dropped);
+            self.dropped = 0;
+        }
+    }
+
+    fn push(&mut self, record: serde_json::Value, limit: usize) {
+        if self.queue.len() >= limit {
+            self.queue.pop_front();
+            self.dropped += 1;
+        }
+        self.queue.push_back(record);
+        self.flush();
+    }
+}
+
+/**
+ * returns the numeric fields (booleans as 0/1) other than schema_version of a TempLog record
+ * for metrics sinks, or None for an event record
+ */
+#[cfg_attr(not(any(feature = "prometheus", feature = "graphite", feature = "statsd")), allow(dead_code))]
+pub fn metrics(record: &serde_json::Value) -> Option<Vec<(&str, f64)>> {
+    let fields = record.as_object()?;
+    if fields.contains_key("type") {
+        return None;
+    }
+    Some(fields.iter().filter_map(|(k, v)| match v {
+        serde_json::Value::Number(_) if k == "schema_version" => None,
+        serde_json::Value::Number(n) => n.as_f64().map(|n| (k.as_str(), n)),
+        serde_json::Value::Bool(b) => Some((k.as_str(), if *b { 1.0 } else { 0.0 })),
+        _ => None,
+    }).collect())
+}
+
+/**
+ * returns when a record was made (unix time), from its `timestamp` field
+ */
+#[cfg_attr(not(any(feature = "graphite", feature = "postgres", feature = "chart")), allow(dead_code))]
+pub fn timestamp(record: &serde_json::Value) -> Option<i64> {
+    let t = record.get("timestamp")?.as_str()?;
+    chrono::DateTime::parse_from_rfc3339(t).ok().map(|t| t.timestamp())
+}
+
+/**
+ * returns the home a record belongs to, if named
+ */
+#[cfg_attr(not(any(feature = "prometheus", feature = "graphite", feature = "statsd", feature = "postgres")), allow(dead_code))]
+pub fn home(record: &serde_json::Value) -> Option<&str> {
+    record.get("home").or_else(|| record.get("zone")).and_then(|h| h.as_str())
+}
+
+/**
+ * prints a record to stdout and hands it to the dispatching thread for registered sinks
+ */
+pub fn emit<T: serde::Serialize>(record: &T) {
+    let str = match serde_json::to_string(record) {
+        Ok(s) => super::redact::text(&s),
+        Err(_) => return,
+    };
+    println!("{}", str);
+    if DISPATCHING.with(|d| d.get()) {
+        return;
+    }
+    if let (Ok(d), Ok(value)) = (DISPATCHER.lock(), serde_json::from_str::<serde_json::Value>(&str)) {
+        if let Some(d) = d.as_ref() {
+            let _ = d.records.send(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct FlakySink {
+        up: std::sync::Arc<std::sync::atomic::AtomicBool>,
+        received: std::sync::Arc<Mutex<Vec<serde_json::Value>>>,
+    }
+
+    impl Sink for FlakySink {
+        fn name(&self) -> &str {
+            "flaky"
+        }
+
+        fn send(&mut self, record: &serde_json::Value) -> Result<(), Error> {
+            if !self.up.load(std::sync::atomic::Ordering::SeqCst) {
+                return Err(Error::Config("down".to_string()));
+            }
+            self.received.lock().unwrap().push(record.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn record_metrics() {
+        let log = serde_json::json!({ "home": "cabin", "awair_temp": 21.5, "awair_co2": null, "execute_control": true, "schema_version": 1 });
+        let mut m = metrics(&log).unwrap();
+        m.sort_by(|a, b| a.0.cmp(b.0));
+        assert_eq!(m, vec![("awair_temp", 21.5), ("execute_control", 1.0)]);
+        assert_eq!(home(&log), Some("cabin"));
+        assert_eq!(timestamp(&log), None);
+        assert_eq!(timestamp(&serde_json::json!({ "timestamp": "2023-11-14T22:13:20.000Z" })), Some(1700000000));
+        assert!(metrics(&serde_json::json!({ "type": "error", "retry_in": 5 })).is_none());
+    }
+
+    #[test]
+    fn queue_while_down() {
+        let up = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let received = std::sync::Arc::new(Mutex::new(Vec::new()));
+        let mut qs = QueuedSink {
+            sink: Box::new(FlakySink { up: up.clone(), received: received.clone() }),
+            queue: VecDeque::new(),
+            dropped: 0,
+        };
+        for i in 0..3 {
+            qs.push(serde_json::Value::from(i), 2);
+        }
+        assert_eq!(qs.queue.len(), 2);
+        assert_eq!(qs.dropped, 1);
+        up.store(true, std::sync::atomic::Ordering::SeqCst);
+        qs.push(serde_json::Value::from(3), 2);
+        let received = received.lock().unwrap();
+        assert_eq!(*received, vec![serde_json::Value::from(2), serde_json::Value::from(3)]);
+        assert!(qs.queue.is_empty());
+    }
+
+    #[test]
+    fn retry_without_new_records() {
+        let up = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let received = std::sync::Arc::new(Mutex::new(Vec::new()));
+        let qs = QueuedSink {
+            sink: Box::new(FlakySink { up: up.clone(), received: received.clone() }),
+            queue: VecDeque::new(),
+            dropped: 0,
+        };
+        let (tx, rx) = std::sync::mpsc::channel();
+        let thread = std::thread::spawn(move || dispatch(rx, vec![qs], 10, Duration::from_millis(10)));
+        tx.send(serde_json::Value::from(1)).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(received.lock().unwrap().is_empty());
+        /* the sink comes back while nothing new is logged */
+        up.store(true, std::sync::atomic::Ordering::SeqCst);
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(*received.lock().unwrap(), vec![serde_json::Value::from(1)]);
+        drop(tx);
+        thread.join().unwrap();
+    }
+}
@@ -0,0 +1,92 @@
+/*
+ * Graphite (carbon) plaintext sink (`graphite_host`). Each TempLog record is sent as
+ * `<prefix>[.<home>].<field> <value> <timestamp>` lines over TCP; events are not sent.
+ * The timestamp is the record's own, so records queued while offline keep their time.
+ */
+use std::io::Write;
+use std::net::{TcpStream, ToSocketAddrs};
+use super::*;
+
+pub struct Graphite {
+    host: String,
+    port: u16,
+    prefix: String,
+    stream: Option<TcpStream>,
+}
+
+impl Graphite {
+    pub fn new(host: &str, port: u16, prefix: &str) -> Graphite {
+        Graphite { host: host.to_string(), port, prefix: prefix.to_string(), stream: None }
+    }
+
+    fn connect(&self) -> Result<TcpStream, Error> {
+        let mut last = std::io::Error::new(std::io::ErrorKind::NotFound, format!("{} not resolved", self.host));
+        for addr in (self.host.as_str(), self.port).to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, SEND_TIMEOUT) {
+                Ok(s) => {
+                    s.set_write_timeout(Some(SEND_TIMEOUT))?;
+                    return Ok(s);
+                },
+                Err(e) => last = e,
+            }
+        }
+        Err(Error::Io(last))
+    }
+}
+
+/**
+ * replaces characters that separate or break Graphite paths
+ */
+fn path_component(s: &str) -> String {
+    s.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' }).collect()
+}
+
+/**
+ * formats metrics as plaintext protocol lines
+ */
+fn lines(prefix: &str, home: Option<&str>, metrics: &[(&str, f64)], timestamp: i64) -> String {
+    let base = match home {
+        Some(h) => format!("{}.{}", prefix, path_component(h)),
+        None => prefix.to_string(),
+    };
+    metrics.iter().map(|(name, value)| format!("{}.{} {} {}\n", base, name, value, timestamp)).collect()
+}
+
+impl Sink for Graphite {
+    fn name(&self) -> &str {
+        "graphite"
+    }
+
+    fn send(&mut self, record: &serde_json::Value) -> Result<(), Error> {
+        let metrics = match metrics(record) {
+            Some(m) => m,
+            None => return Ok(()),
+        };
+        let time = timestamp(record).unwrap_or_else(|| chrono::Utc::now().timestamp());
+        let buf = lines(&self.prefix, home(record), &metrics, time);
+        if self.stream.is_none() {
+            self.stream = Some(self.connect()?);
+        }
+        if let Some(stream) = self.stream.as_mut() {
+            if let Err(e) = stream.write_all(buf.as_bytes()) {
+                /* reconnect on the next record */
+                self.stream = None;
+                return Err(Error::Io(e));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn plaintext_lines() {
+        assert_eq!(lines("daikawa", Some("Lake house"), &[("awair_temp", 21.5)], 1700000000),
+            "daikawa.Lake_house.awair_temp 21.5 1700000000\n");
+        assert_eq!(lines("daikawa", None, &[("execute_control", 0.0)], 1),
+            "daikawa.execute_control 0 1\n");
+    }
+}
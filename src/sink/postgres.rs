@@ -0,0 +1,65 @@
+/*
+ * PostgreSQL/TimescaleDB sink (`postgres_url`, needs the "postgres" feature).
+ *
+ * Every record is inserted into `postgres_table` as (time, home, kind, record), where time is
+ * the record's own timestamp (or when it was inserted, for records without one), kind
+ * is "log" for TempLog records and the event type for events, and record is the whole
+ * record as jsonb. The table is created on first connection, and turned into a hypertable
+ * if the TimescaleDB extension is installed.
+ */
+use ::postgres::{Client, NoTls};
+use super::*;
+
+pub struct Postgres {
+    url: String,
+    table: String,
+    client: Option<Client>,
+}
+
+impl Postgres {
+    pub fn new(url: &str, table: &str) -> Postgres {
+        Postgres { url: url.to_string(), table: table.to_string(), client: None }
+    }
+
+    fn connect(&self) -> Result<Client, ::postgres::Error> {
+        let mut config: ::postgres::Config = self.url.parse()?;
+        let mut client = config.connect_timeout(SEND_TIMEOUT).connect(NoTls)?;
+        client.batch_execute(&format!("SET statement_timeout = {}", SEND_TIMEOUT.as_millis()))?;
+        client.batch_execute(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (time timestamptz NOT NULL, home text, kind text NOT NULL, record jsonb NOT NULL)",
+            self.table))?;
+        let timescale = client.query_opt("SELECT 1 FROM pg_extension WHERE extname = 'timescaledb'", &[])?.is_some();
+        if timescale {
+            client.execute(&format!("SELECT create_hypertable('{}', 'time', if_not_exists => TRUE)", self.table), &[])?;
+        }
+        Ok(client)
+    }
+}
+
+fn db_error(e: ::postgres::Error) -> Error {
+    Error::Internal(format!("PostgreSQL: {}", e))
+}
+
+impl Sink for Postgres {
+    fn name(&self) -> &str {
+        "postgres"
+    }
+
+    fn send(&mut self, record: &serde_json::Value) -> Result<(), Error> {
+        if self.client.is_none() {
+            self.client = Some(self.connect().map_err(db_error)?);
+        }
+        let kind = record.get("type").and_then(|t| t.as_str()).unwrap_or("log");
+        let time = timestamp(record).map(|t| t as f64);
+        let sql = format!("INSERT INTO {} (time, home, kind, record) VALUES (COALESCE(to_timestamp($1), now()), $2, $3, $4)",
+            self.table);
+        if let Some(client) = self.client.as_mut() {
+            if let Err(e) = client.execute(sql.as_str(), &[&time, &home(record), &kind, record]) {
+                /* reconnect on the next record */
+                self.client = None;
+                return Err(db_error(e));
+            }
+        }
+        Ok(())
+    }
+}
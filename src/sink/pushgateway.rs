@@ -0,0 +1,74 @@
+/*
+ * Prometheus Pushgateway sink (`pushgateway_url`), for setups where Prometheus cannot
+ * scrape daikawa (e.g. behind NAT). Each TempLog record replaces the metrics of the
+ * group job="daikawa" (and home="<name>" for named homes); events are not pushed.
+ */
+use super::*;
+use crate::webapi;
+
+pub struct Pushgateway {
+    url: String,
+    /* the runtime of the HTTP client, which the sink thread blocks on */
+    runtime: tokio::runtime::Handle,
+}
+
+impl Pushgateway {
+    /**
+     * must be called within the runtime the HTTP client runs on
+     */
+    pub fn new(url: &str) -> Pushgateway {
+        Pushgateway { url: url.trim_end_matches('/').to_string(), runtime: tokio::runtime::Handle::current() }
+    }
+}
+
+/**
+ * percent-encodes a label value for use in a URL path
+ */
+fn encode(value: &str) -> String {
+    value.bytes().map(|b| match b {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+        _ => format!("%{:02X}", b),
+    }).collect()
+}
+
+/**
+ * formats metrics in the Prometheus text exposition format
+ */
+fn exposition(metrics: &[(&str, f64)]) -> String {
+    metrics.iter().map(|(name, value)| format!("daikawa_{} {}\n", name, value)).collect()
+}
+
+impl Sink for Pushgateway {
+    fn name(&self) -> &str {
+        "pushgateway"
+    }
+
+    fn send(&mut self, record: &serde_json::Value) -> Result<(), Error> {
+        let metrics = match metrics(record) {
+            Some(m) => m,
+            None => return Ok(()),
+        };
+        let url = match home(record) {
+            Some(h) => format!("{}/metrics/job/daikawa/home/{}", self.url, encode(h)),
+            None => format!("{}/metrics/job/daikawa", self.url),
+        };
+        let (res, buf) = self.runtime.block_on(webapi::access_timeout(&url, webapi::HTTPMethod::POST, None,
+            Some(&exposition(&metrics)), Some(SEND_TIMEOUT)))?;
+        if res != 200 && res != 202 {
+            return Err(Error::from_status(res, String::from_utf8(buf).unwrap_or_default()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn push_format() {
+        assert_eq!(encode("Lake house/1"), "Lake%20house%2F1");
+        assert_eq!(exposition(&[("awair_temp", 21.5), ("execute_control", 1.0)]),
+            "daikawa_awair_temp 21.5\ndaikawa_execute_control 1\n");
+    }
+}
@@ -0,0 +1,107 @@
+/*
+ * StatsD sink (`statsd_host`), sent over UDP. Numeric TempLog fields (readings, setpoints,
+ * whether control was executed) become gauges, and error and warning events related to an
+ * API increment the `api_errors` and `api_warnings` counters. With `statsd_dogstatsd`, the home and API are
+ * DogStatsD tags; otherwise they are part of the metric name.
+ */
+use std::net::UdpSocket;
+use super::*;
+
+pub struct StatsD {
+    target: String,
+    prefix: String,
+    dogstatsd: bool,
+    socket: Option<UdpSocket>,
+}
+
+impl StatsD {
+    pub fn new(host: &str, port: u16, prefix: &str, dogstatsd: bool) -> StatsD {
+        StatsD { target: format!("{}:{}", host, port), prefix: prefix.to_string(), dogstatsd, socket: None }
+    }
+
+    /**
+     * returns `name` with `tags`, as DogStatsD tags or as metric name components
+     */
+    fn metric(&self, name: &str, tags: &[(&str, &str)], value: &str) -> String {
+        if self.dogstatsd {
+            let tags: Vec<String> = tags.iter().map(|(k, v)| format!("{}:{}", k, v)).collect();
+            if tags.is_empty() {
+                format!("{}.{}:{}", self.prefix, name, value)
+            } else {
+                format!("{}.{}:{}|#{}", self.prefix, name, value, tags.join(","))
+            }
+        } else {
+            let mut path = self.prefix.clone();
+            for (_, v) in tags.iter() {
+                path.push('.');
+                path.push_str(&v.replace(|c: char| !c.is_ascii_alphanumeric() && c != '-' && c != '_', "_"));
+            }
+            format!("{}.{}:{}", path, name, value)
+        }
+    }
+
+    /**
+     * returns the StatsD lines for a record
+     */
+    fn lines(&self, record: &serde_json::Value) -> Vec<String> {
+        let home: Vec<(&str, &str)> = home(record).map(|h| ("home", h)).into_iter().collect();
+        if let Some(metrics) = metrics(record) {
+            return metrics.iter().map(|(name, value)| self.metric(name, &home, &format!("{}|g", value))).collect();
+        }
+        let event_type = record.get("type").and_then(|t| t.as_str());
+        match (event_type, record.get("endpoint").and_then(|e| e.as_str())) {
+            (Some(level @ ("error" | "warning")), Some(endpoint)) => {
+                let mut tags = home;
+                tags.push(("endpoint", endpoint));
+                let name = if level == "error" { "api_errors" } else { "api_warnings" };
+                vec![self.metric(name, &tags, "1|c")]
+            },
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl Sink for StatsD {
+    fn name(&self) -> &str {
+        "statsd"
+    }
+
+    fn send(&mut self, record: &serde_json::Value) -> Result<(), Error> {
+        let lines = self.lines(record);
+        if lines.is_empty() {
+            return Ok(());
+        }
+        if self.socket.is_none() {
+            let socket = UdpSocket::bind("0.0.0.0:0")?;
+            socket.connect(&self.target)?;
+            self.socket = Some(socket);
+        }
+        if let Some(socket) = &self.socket {
+            /* one datagram per metric, so a large record does not exceed the MTU */
+            for line in lines.iter() {
+                socket.send(line.as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn statsd_lines() {
+        let log = serde_json::json!({ "home": "cabin", "awair_temp": 21.5 });
+        let event = serde_json::json!({ "type": "error", "zone": "cabin", "endpoint": "awair", "message": "" });
+        let plain = StatsD::new("localhost", 8125, "daikawa", false);
+        assert_eq!(plain.lines(&log), vec!["daikawa.cabin.awair_temp:21.5|g"]);
+        assert_eq!(plain.lines(&event), vec!["daikawa.cabin.awair.api_errors:1|c"]);
+        let dog = StatsD::new("localhost", 8125, "daikawa", true);
+        assert_eq!(dog.lines(&log), vec!["daikawa.awair_temp:21.5|g|#home:cabin"]);
+        assert_eq!(dog.lines(&event), vec!["daikawa.api_errors:1|c|#home:cabin,endpoint:awair"]);
+        let warning = serde_json::json!({ "type": "warning", "endpoint": "daikin", "message": "" });
+        assert_eq!(dog.lines(&warning), vec!["daikawa.api_warnings:1|c|#endpoint:daikin"]);
+        assert!(dog.lines(&serde_json::json!({ "type": "info", "message": "" })).is_empty());
+    }
+}
@@ -0,0 +1,88 @@
+/*
+ * Local command socket (`command_socket`): a Unix socket accepting runtime commands (see
+ * command.rs), one JSON object per connection, answered with "ok" or "error: <reason>".
+ * `daikawa --pause[=MINUTES]` and `daikawa --resume` send commands through it.
+ *
+ * Access is controlled by the socket file's permissions (created as the daemon's umask allows).
+ */
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+use tokio::sync::mpsc::UnboundedSender;
+use super::*;
+
+/* longest command accepted */
+const MAX_COMMAND: u64 = 4096;
+const SOCKET_TIMEOUT: Duration = Duration::from_secs(5);
+
+async fn serve(stream: &mut tokio::net::UnixStream, homes: &[(Option<String>, UnboundedSender<controller::Event>)]) {
+    let mut line = String::new();
+    let mut reader = tokio::io::BufReader::new(&mut *stream).take(MAX_COMMAND);
+    let read = tokio::time::timeout(SOCKET_TIMEOUT, reader.read_line(&mut line)).await
+        .unwrap_or_else(|_| Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "no command received")));
+    let reply = match read {
+        Ok(_) => match command::dispatch(homes, line.trim().as_bytes()) {
+            Ok(()) => "ok".to_string(),
+            Err(e) => {
+                print_event(&EventLog::warning(format!("Ignoring command from command_socket: {}", e)));
+                format!("error: {}", e)
+            },
+        },
+        Err(e) => format!("error: {}", e),
+    };
+    let _ = stream.write_all(format!("{}\n", reply).as_bytes()).await;
+}
+
+/**
+ * starts accepting commands on `path` in a background task
+ */
+pub fn spawn(path: &str, homes: Vec<(Option<String>, UnboundedSender<controller::Event>)>) -> Result<(), Error> {
+    /* a socket left behind by a previous run */
+    if UnixStream::connect(path).is_err() {
+        let _ = std::fs::remove_file(path);
+    }
+    let listener = tokio::net::UnixListener::bind(path)?;
+    tokio::spawn(async move {
+        loop {
+            if let Ok((mut s, _)) = listener.accept().await {
+                serve(&mut s, &homes).await;
+            }
+        }
+    });
+    Ok(())
+}
+
+/**
+ * sends a command to a running daikawa and returns its reply
+ */
+pub fn send(path: &str, message: &str) -> Result<String, Error> {
+    let mut stream = UnixStream::connect(path)?;
+    stream.set_read_timeout(Some(SOCKET_TIMEOUT))?;
+    writeln!(stream, "{}", message)?;
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply)?;
+    Ok(reply.trim().to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn command_socket() {
+        let path = std::env::temp_dir().join(format!("daikawa-socket-test-{}", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        spawn(&path, vec![(Some("cabin".to_string()), tx)]).unwrap();
+        /* send blocks, as in `daikawa --pause` */
+        let request = |message: &'static str| {
+            let path = path.clone();
+            tokio::task::spawn_blocking(move || send(&path, message))
+        };
+        assert_eq!(request(r#"{"command": "pause", "minutes": 10}"#).await.unwrap().unwrap(), "ok");
+        assert!(matches!(rx.recv().await.unwrap(), controller::Event::Command(command::Command::Pause { minutes: Some(10) })));
+        assert!(request(r#"{"command": "resume", "home": "elsewhere"}"#).await.unwrap().unwrap().starts_with("error:"));
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,57 @@
+/*
+ * Machine-readable status file (`status_file`), rewritten atomically after every control
+ * cycle so external monitoring can read the current state without a network API.
+ */
+use serde::Serialize;
+use super::*;
+
+#[derive(Debug, Serialize)]
+pub struct Status<'a> {
+    home: Option<&'a str>,
+    /* unix time this status was written */
+    updated_at: i64,
+    phase: controller::Phase,
+    /* true while daikawa is adjusting setpoints */
+    controlling: bool,
+    awair_temp: Option<f64>,
+    daikin_indoor_temp: f64,
+    daikin_outdoor_temp: f64,
+    heat_setpoint: f64,
+    cool_setpoint: f64,
+    /* setpoints daikawa applied last and when */
+    last_applied: Option<AppliedSetpoints>,
+    last_awair_success: Option<i64>,
+    last_daikin_success: Option<i64>,
+    /* unix time of the next control cycle */
+    next_wake: i64,
+}
+
+impl<'a> Status<'a> {
+    pub(crate) fn new(config: &'a Config, thermostat: &dyn Thermostat, state: &ControlState, now: i64, next_wake: i64) -> Status<'a> {
+        Status {
+            home: config.name.as_deref(),
+            updated_at: now,
+            phase: state.phase,
+            controlling: state.phase == controller::Phase::Controlling,
+            awair_temp: state.last_awair_temp.map(|(_, t)| t),
+            daikin_indoor_temp: thermostat.get_temp_indoor(),
+            daikin_outdoor_temp: thermostat.get_temp_outdoor(),
+            heat_setpoint: thermostat.get_heat_setpoint(),
+            cool_setpoint: thermostat.get_cool_setpoint(),
+            last_applied: state.last_applied,
+            last_awair_success: state.last_awair_success,
+            last_daikin_success: state.last_daikin_success,
+            next_wake,
+        }
+    }
+
+    /**
+     * writes the status atomically
+     */
+    pub(crate) fn save(&self, path: &str) -> Result<(), String> {
+        let json = serde_json::to_vec_pretty(self).map_err(|e| e.to_string())?;
+        let tmp = format!("{}.tmp", path);
+        std::fs::write(&tmp, json).map_err(|e| format!("{}: {}", tmp, e))?;
+        std::fs::rename(&tmp, path).map_err(|e| format!("{}: {}", path, e))
+    }
+}
@@ -0,0 +1,82 @@
+/*
+ * Coordination between homes controlled by one instance.
+ *
+ * Each home runs its control loop in its own task. Control cycles take one of a bounded
+ * number of slots (`max_parallel`), and API requests are paced per account (token or
+ * email), so homes sharing an account do not burst requests at the same moment.
+ */
+#[cfg(any(feature = "awair", feature = "daikin-skyport"))]
+use std::collections::HashMap;
+#[cfg(any(feature = "awair", feature = "daikin-skyport"))]
+use std::sync::Mutex;
+use std::sync::OnceLock;
+#[cfg(any(feature = "awair", feature = "daikin-skyport"))]
+use std::time::{Duration, Instant};
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+static SLOTS: OnceLock<Semaphore> = OnceLock::new();
+/* only the cloud APIs pace their requests */
+#[cfg(any(feature = "awair", feature = "daikin-skyport"))]
+static LAST_REQUEST: Mutex<Option<HashMap<String, Instant>>> = Mutex::new(None);
+
+/**
+ * sets how many control cycles may run at the same time
+ */
+pub fn set_max_parallel(limit: usize) {
+    let _ = SLOTS.set(Semaphore::new(limit.clamp(1, Semaphore::MAX_PERMITS)));
+}
+
+/* a running control cycle; the slot is released when dropped */
+pub type Slot = Option<SemaphorePermit<'static>>;
+
+/**
+ * waits for a free control cycle slot
+ */
+pub async fn acquire() -> Slot {
+    /* the semaphore is never closed */
+    SLOTS.get_or_init(|| Semaphore::new(Semaphore::MAX_PERMITS)).acquire().await.ok()
+}
+
+/**
+ * returns how long to wait before the next request, given the previous one
+ */
+#[cfg(any(feature = "awair", feature = "daikin-skyport"))]
+fn wait_time(last: Option<Instant>, now: Instant, spacing: Duration) -> Duration {
+    match last {
+        Some(t) => (t + spacing).saturating_duration_since(now),
+        None => Duration::ZERO,
+    }
+}
+
+/**
+ * waits until at least `spacing` has passed since the previous request on `account`
+ */
+#[cfg(any(feature = "awair", feature = "daikin-skyport"))]
+pub async fn pace(account: &str, spacing: Duration) {
+    let wait = {
+        let mut last = LAST_REQUEST.lock().unwrap_or_else(|e| e.into_inner());
+        let map = last.get_or_insert_with(HashMap::new);
+        let now = Instant::now();
+        let wait = wait_time(map.get(account).copied(), now, spacing);
+        /* reserve the slot right away, so concurrent callers queue up behind it */
+        map.insert(account.to_string(), now + wait);
+        wait
+    };
+    if !wait.is_zero() {
+        tokio::time::sleep(wait).await;
+    }
+}
+
+#[cfg(all(test, any(feature = "awair", feature = "daikin-skyport")))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn request_pacing() {
+        let now = Instant::now();
+        let spacing = Duration::from_secs(1);
+        assert_eq!(wait_time(None, now, spacing), Duration::ZERO);
+        assert_eq!(wait_time(Some(now), now, spacing), spacing);
+        assert_eq!(wait_time(Some(now), now + Duration::from_secs(2), spacing), Duration::ZERO);
+    }
+}
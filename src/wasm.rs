@@ -0,0 +1,135 @@
+/*
+ * Experimental WASM plugin host (`wasm_plugin`, needs the "wasm" feature).
+ *
+ * A plugin is a WebAssembly component implementing the `plugin` world in wit/daikawa.wit.
+ * With `sensor = "wasm"` its read-sensor export is the sensor backend, and with
+ * `wasm_policy = true` its policy export may replace the setpoints daikawa computed, if they
+ * are sane (see check_setpoints).
+ * Plugins are loaded once per path and shared by homes using the same file.
+ *
+ * Each call runs on a blocking thread with FUEL_PER_CALL units of fuel (about one per
+ * WebAssembly instruction); a plugin running out of it traps, fails the call and is loaded
+ * again on the next one.
+ */
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use wasmtime::component::{Component, Linker};
+use wasmtime::{Engine, Store};
+use super::*;
+
+/* also defines the WIT record `Readings`, so the crate's is spelled super::Readings here */
+wasmtime::component::bindgen!({ path: "wit", world: "plugin" });
+
+/* fuel for instantiating a plugin or one call into it */
+const FUEL_PER_CALL: u64 = 100_000_000;
+
+struct Loaded {
+    store: Store<()>,
+    bindings: Plugin,
+}
+
+static PLUGINS: Mutex<Option<HashMap<String, Arc<Mutex<Loaded>>>>> = Mutex::new(None);
+
+fn plugin_error(e: impl std::fmt::Display) -> Error {
+    Error::Internal(format!("wasm plugin: {}", e))
+}
+
+fn load(path: &str) -> Result<Arc<Mutex<Loaded>>, Error> {
+    let mut plugins = PLUGINS.lock().unwrap_or_else(|e| e.into_inner());
+    let plugins = plugins.get_or_insert_with(HashMap::new);
+    if let Some(p) = plugins.get(path) {
+        return Ok(p.clone());
+    }
+    let mut wasm_config = wasmtime::Config::new();
+    wasm_config.wasm_component_model(true);
+    wasm_config.consume_fuel(true);
+    let engine = Engine::new(&wasm_config).map_err(plugin_error)?;
+    let component = Component::from_file(&engine, path).map_err(plugin_error)?;
+    /* plugins get no host imports: no file system, network or clock */
+    let linker = Linker::new(&engine);
+    let mut store = Store::new(&engine, ());
+    store.add_fuel(FUEL_PER_CALL).map_err(plugin_error)?;
+    let (bindings, _) = Plugin::instantiate(&mut store, &component, &linker).map_err(plugin_error)?;
+    let loaded = Arc::new(Mutex::new(Loaded { store, bindings }));
+    plugins.insert(path.to_string(), loaded.clone());
+    Ok(loaded)
+}
+
+/**
+ * runs `f` on the plugin at `path` on a blocking thread, with the fuel topped up to
+ * FUEL_PER_CALL. A plugin that trapped is dropped, to be loaded afresh by the next call.
+ */
+async fn call<T, F>(path: &str, f: F) -> Result<T, Error>
+where
+    T: Send + 'static,
+    F: FnOnce(&mut Store<()>, &Plugin) -> wasmtime::Result<T> + Send + 'static,
+{
+    let path = path.to_string();
+    tokio::task::spawn_blocking(move || {
+        let plugin = load(&path)?;
+        let mut p = plugin.lock().unwrap_or_else(|e| e.into_inner());
+        let Loaded { store, bindings } = &mut *p;
+        /* what a call leaves over is not carried to the next */
+        let left = store.consume_fuel(0).map_err(plugin_error)?;
+        store.add_fuel(FUEL_PER_CALL - left).map_err(plugin_error)?;
+        f(store, bindings).map_err(|e| {
+            if let Some(plugins) = PLUGINS.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+                plugins.remove(&path);
+            }
+            plugin_error(e)
+        })
+    }).await.map_err(plugin_error)?
+}
+
+/**
+ * reads the sensor implemented by the plugin at `path`
+ */
+pub(crate) async fn read_sensor(path: &str) -> Result<super::Readings, Error> {
+    match call(path, |store, bindings| bindings.call_read_sensor(store)).await? {
+        Ok(r) => Ok(super::Readings { temp: r.temp, humid: r.humid, co2: r.co2, pm25: r.pm25, voc: r.voc }),
+        Err(message) => Err(Error::Offline { device: format!("wasm plugin {}", path), message }),
+    }
+}
+
+/* the plugin at `wasm_plugin` as a TemperatureSensor */
+pub(crate) struct WasmSensor<'a> {
+    pub(crate) plugin: &'a str,
+}
+
+#[async_trait::async_trait]
+impl TemperatureSensor for WasmSensor<'_> {
+    async fn read(&self) -> Result<super::Readings, Error> {
+        read_sensor(self.plugin).await
+    }
+
+    fn endpoint(&self) -> &'static str {
+        "wasm"
+    }
+}
+
+/* inputs to a policy plugin */
+pub(crate) struct PolicyInputs {
+    pub(crate) sensor_temp: f64,
+    pub(crate) daikin_temp: f64,
+    pub(crate) outdoor_temp: f64,
+    pub(crate) targets: (f64, f64),
+    pub(crate) setpoints: (f64, f64),
+}
+
+/**
+ * asks the plugin at `path` for setpoints; None keeps daikawa's own. Setpoints failing
+ * check_setpoints are an error, so the caller keeps its own then too.
+ */
+pub(crate) async fn policy(path: &str, inputs: &PolicyInputs) -> Result<Option<(f64, f64)>, Error> {
+    let inputs = Inputs {
+        sensor_temp: inputs.sensor_temp,
+        daikin_temp: inputs.daikin_temp,
+        outdoor_temp: inputs.outdoor_temp,
+        target_heat: inputs.targets.0,
+        target_cool: inputs.targets.1,
+        heat_setpoint: inputs.setpoints.0,
+        cool_setpoint: inputs.setpoints.1,
+    };
+    let sp = call(path, move |store, bindings| bindings.call_policy(store, inputs)).await?;
+    sp.map(|s| check_setpoints((s.heat, s.cool))).transpose()
+}
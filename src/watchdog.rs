@@ -0,0 +1,230 @@
+/*
+ * Freeze-risk watchdog for unoccupied properties (`--watchdog FILE`).
+ *
+ * Only Daikin indoor temperatures are monitored; no Awair sensor, targets or control
+ * window are needed, and setpoints are never changed. The watchdog file lists properties:
+ *
+ *   freeze_alert = 7.0
+ *   interval = 30
+ *   notify_command = '/usr/local/bin/send-alert'
+ *
+ *   [[properties]]
+ *   name = 'cabin'
+ *   daikin_email = 'cabin@example.com'
+ *   daikin_password = 'secret'
+ *
+ * An alert is raised once when a property's indoor temperature falls to `freeze_alert`,
+ * and cleared when it is FREEZE_CLEAR_MARGIN above it again. A property whose thermostat
+ * cannot be read for `failed_checks_alert` checks in a row is alerted on once per outage,
+ * as its temperature is no longer watched.
+ */
+use serde::Deserialize;
+use super::*;
+
+/* degrees above freeze_alert at which an alert is cleared, so it does not flap */
+const FREEZE_CLEAR_MARGIN: f64 = 1.0;
+
+#[derive(Debug, Deserialize)]
+struct Property {
+    name: String,
+    daikin_email: String,
+    daikin_password: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct WatchConfig {
+    #[serde(default = "default_freeze_alert")]
+    freeze_alert: f64,
+    /* minutes between checks */
+    #[serde(default = "default_watch_interval")]
+    interval: u32,
+    notify_command: Option<String>,
+    /* consecutive failed checks after which a property is alerted on as unreachable */
+    #[serde(default = "default_failed_checks_alert")]
+    failed_checks_alert: u32,
+    properties: Vec<Property>,
+}
+
+fn default_freeze_alert() -> f64 {
+    7.0
+}
+
+fn default_watch_interval() -> u32 {
+    30
+}
+
+fn default_failed_checks_alert() -> u32 {
+    3
+}
+
+struct Watched<'a> {
+    property: &'a Property,
+    skyport: Option<daikin::SkyPort>,
+    alerted: bool,
+    /* checks failed in a row */
+    failures: u32,
+}
+
+fn read_watch_config(path: &str) -> Result<WatchConfig, Error> {
+    let buf = std::fs::read_to_string(path).map_err(|e| Error::Config(format!("Failed to read {}: {}", path, e)))?;
+    let config: WatchConfig = toml::from_str(&buf).map_err(|e| Error::Config(format!("Failed to parse {}: {}", path, e)))?;
+    if config.properties.is_empty() {
+        return Err(Error::Config(format!("No properties in {}", path)));
+    }
+    if config.interval == 0 {
+        return Err(Error::Config(format!("interval in {} must be greater than 0", path)));
+    }
+    if config.failed_checks_alert == 0 {
+        return Err(Error::Config(format!("failed_checks_alert in {} must be greater than 0", path)));
+    }
+    Ok(config)
+}
+
+/**
+ * returns the alert state at `temp`: raised at or below the threshold, and kept until
+ * the temperature is clear of it
+ */
+fn freeze_state(alerted: bool, temp: f64, threshold: f64) -> bool {
+    if alerted {
+        temp < threshold + FREEZE_CLEAR_MARGIN
+    } else {
+        temp <= threshold
+    }
+}
+
+/**
+ * logs a warning about a property, also running notify_command if it is set
+ */
+fn alert_property(config: &WatchConfig, name: &str, message: String) {
+    print_event(&EventLog { zone: Some(name.to_string()), ..EventLog::warning(message.clone()) });
+    if let Some(cmd) = &config.notify_command {
+        run_notify_command(cmd, &message);
+    }
+}
+
+impl<'a> Watched<'a> {
+    /**
+     * counts a failed check; returns true if it alerted, once when `failed_checks_alert`
+     * have failed in a row
+     */
+    fn record_failure(&mut self, config: &WatchConfig) -> bool {
+        self.failures += 1;
+        if self.failures != config.failed_checks_alert {
+            return false;
+        }
+        alert_property(config, &self.property.name, format!("{}: thermostat unreachable for {} checks in a row, temperature not watched",
+            self.property.name, self.failures));
+        true
+    }
+
+    fn record_success(&mut self, config: &WatchConfig) {
+        if self.failures >= config.failed_checks_alert {
+            let message = format!("{}: thermostat reachable again", self.property.name);
+            print_event(&EventLog { zone: Some(self.property.name.clone()), ..EventLog::info(message.clone()) });
+            if let Some(cmd) = &config.notify_command {
+                run_notify_command(cmd, &message);
+            }
+        }
+        self.failures = 0;
+    }
+
+    async fn check(&mut self, config: &WatchConfig) {
+        let p = self.property;
+        if self.skyport.is_none() {
+            match daikin::SkyPort::new(&p.daikin_email, &p.daikin_password).await {
+                Ok(s) => self.skyport = Some(s),
+                Err(e) => {
+                    print_event(&EventLog {
+                        zone: Some(p.name.clone()),
+                        endpoint: Some("daikin"),
+                        http_status: e.http_status(),
+                        ..EventLog::warning(format!("Failed to connect to Daikin Skyport: {}", e))
+                    });
+                    self.record_failure(config);
+                    return;
+                }
+            }
+        }
+        let skyport = match self.skyport.as_mut() {
+            Some(s) => s,
+            None => return,
+        };
+        if let Err(e) = skyport.sync().await {
+            print_event(&EventLog {
+                zone: Some(p.name.clone()),
+                endpoint: Some("daikin"),
+                http_status: e.http_status(),
+                ..EventLog::warning(format!("Daikin Skyport sync failed: {}", e))
+            });
+            self.record_failure(config);
+            return;
+        }
+        let temp = skyport.get_temp_indoor();
+        self.record_success(config);
+        let alerted = freeze_state(self.alerted, temp, config.freeze_alert);
+        if alerted && !self.alerted {
+            alert_property(config, &p.name, format!("{}: indoor temperature is {:.1} C, at or below {} C", p.name, temp, config.freeze_alert));
+        } else if !alerted && self.alerted {
+            print_event(&EventLog {
+                zone: Some(p.name.clone()),
+                ..EventLog::info(format!("{}: indoor temperature is back to {:.1} C", p.name, temp))
+            });
+        }
+        self.alerted = alerted;
+    }
+}
+
+/**
+ * monitors the properties listed in `path` until the process is terminated
+ */
+pub async fn run(path: &str) -> Result<(), Error> {
+    let config = read_watch_config(path)?;
+    let mut watched: Vec<Watched> = config.properties.iter()
+        .map(|property| Watched { property, skyport: None, alerted: false, failures: 0 })
+        .collect();
+    loop {
+        for w in watched.iter_mut() {
+            w.check(&config).await;
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(config.interval as u64 * 60)).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn freeze_hysteresis() {
+        assert!(!freeze_state(false, 8.0, 7.0));
+        assert!(freeze_state(false, 7.0, 7.0));
+        assert!(freeze_state(true, 7.5, 7.0));
+        assert!(!freeze_state(true, 8.0, 7.0));
+
+        let config: WatchConfig = toml::from_str(r#"
+            [[properties]]
+            name = "cabin"
+            daikin_email = "cabin@example.com"
+            daikin_password = "secret"
+        "#).unwrap();
+        assert_eq!(config.freeze_alert, 7.0);
+        assert_eq!(config.interval, 30);
+        assert_eq!(config.failed_checks_alert, 3);
+    }
+
+    #[test]
+    fn unreachable_alert() {
+        let config: WatchConfig = toml::from_str(r#"
+            failed_checks_alert = 2
+            [[properties]]
+            name = "cabin"
+            daikin_email = "cabin@example.com"
+            daikin_password = "secret"
+        "#).unwrap();
+        let mut w = Watched { property: &config.properties[0], skyport: None, alerted: false, failures: 0 };
+        let alerts: Vec<bool> = (0..3).map(|_| w.record_failure(&config)).collect();
+        assert_eq!(alerts, [false, true, false]);
+        w.record_success(&config);
+        assert!(!w.record_failure(&config));
+    }
+}